@@ -1,35 +1,96 @@
-use alloc::alloc::{ GlobalAlloc, Layout};
-use core::ptr::null_mut;
-
-pub struct Dummy;
+pub const HEAP_START: usize = 0x_4444_4444_0000;
+/// Number of bytes mapped for the heap by `init_heap`.
+///
+/// The heap only grows once `memory::init_global` has been called, so this
+/// must cover every allocation made before that. Afterwards the heap grows on
+/// demand up to `heap_limit`.
+pub const HEAP_SIZE: usize = 1024 * 1024; // 1 MiB
+/// Size of the virtual window reserved for the heap at `HEAP_START`. The
+/// heap never grows beyond this window.
+pub const HEAP_MAX_SIZE: usize = 64 * 1024 * 1024; // 64 MiB
+/// The minimum number of bytes mapped whenever the heap grows.
+const HEAP_GROW_STEP: usize = 64 * 1024;
 
-unsafe impl GlobalAlloc for Dummy {
-    unsafe fn alloc(&self, _layout: Layout) -> *mut u8 {
-        null_mut()
-    }
+pub mod bump;
+pub mod linked_list;
+pub mod fixed_size_block;
+pub mod tracking;
 
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
-        panic!("dealloc should be never called!");
-    }
-}
+pub use self::tracking::{stats, HeapStats};
 
-pub const HEAP_START: usize = 0x_4444_4444_0000;
-pub const HEAP_SIZE: usize = 100 * 1024; // 100 kib
+/// The allocator backend used as `#[global_allocator]`.
+///
+/// It is selected with the `alloc-bump`, `alloc-linked-list` and
+/// `alloc-fixed-block` cargo features. If more than one is enabled, the first
+/// one in this order wins, so e.g. `cargo test --features alloc-bump` works
+/// without disabling the default features.
+#[cfg(feature = "alloc-bump")]
+pub type HeapAllocator = bump::BumpAllocator;
+#[cfg(all(feature = "alloc-linked-list", not(feature = "alloc-bump")))]
+pub type HeapAllocator = linked_list::LinkedListAllocator;
+#[cfg(not(any(feature = "alloc-bump", feature = "alloc-linked-list")))]
+pub type HeapAllocator = fixed_size_block::FixedSizeBlockAllocator;
 
+use core::sync::atomic::{AtomicUsize, Ordering};
 use x86_64::{
     structures::paging::{
         mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB,
     },
     VirtAddr,
 };
+use crate::memory::{self, vm::{self, RegionKind, VmError}};
+
+static HEAP_LIMIT: AtomicUsize = AtomicUsize::new(HEAP_MAX_SIZE);
+
+/// Sets the upper bound for the heap size in bytes.
+///
+/// The limit is clamped to `HEAP_MAX_SIZE`. Lowering it below the current
+/// heap size does not shrink the heap, it only prevents further growth.
+pub fn set_heap_limit(limit: usize) {
+    HEAP_LIMIT.store(limit.min(HEAP_MAX_SIZE), Ordering::SeqCst);
+}
+
+/// Returns the upper bound for the heap size in bytes.
+pub fn heap_limit() -> usize {
+    HEAP_LIMIT.load(Ordering::SeqCst)
+}
+
+/// Returns the number of bytes currently mapped for the heap.
+pub fn heap_size() -> usize {
+    super::ALLOCATOR.lock().size()
+}
 
 pub fn init_heap(
     mapper: &mut impl Mapper<Size4KiB>,
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), VmError> {
+    // 保留整个堆窗口，防止其他区域占用堆增长所需的地址
+    vm::reserve_fixed(
+        "heap",
+        VirtAddr::new(HEAP_START as u64),
+        HEAP_MAX_SIZE as u64,
+        RegionKind::Heap,
+        PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+    )?;
+    map_heap_range(HEAP_START, HEAP_SIZE, mapper, frame_allocator)?;
+
+    unsafe {
+        super::ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+    }
+
+    Ok(())
+}
+
+/// Maps the pages in `[start, start + size)` to newly allocated frames.
+fn map_heap_range(
+    start: usize,
+    size: usize,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
 ) -> Result<(), MapToError<Size4KiB>> {
     let page_range = {
-        let heap_start = VirtAddr::new(HEAP_START as u64);
-        let heap_end = heap_start + HEAP_SIZE - 1u64;
+        let heap_start = VirtAddr::new(start as u64);
+        let heap_end = heap_start + size - 1u64;
         let heap_start_page = Page::containing_address(heap_start);
         let heap_end_page = Page::containing_address(heap_end);
         Page::range_inclusive(heap_start_page, heap_end_page)
@@ -39,15 +100,67 @@ pub fn init_heap(
         let frame = frame_allocator
             .allocate_frame()
             .ok_or(MapToError::FrameAllocationFailed)?;
-        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
         unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
     }
 
-    unsafe {
-        super::ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+    Ok(())
+}
+
+/// Maps more of the heap window directly after `top` so that at least
+/// `min_size` further bytes are available.
+///
+/// `size` is the current size of the heap. Returns the number of bytes that
+/// were mapped, or `None` if the heap limit is reached, the global
+/// `memory::MAPPER` and `memory::FRAME_ALLOCATOR` are not set up yet (see
+/// `memory::init_global`) or no frames are left.
+///
+/// This runs while the allocator lock is held, so the global lock order is
+/// `ALLOCATOR`, then `memory::MAPPER`, then `memory::FRAME_ALLOCATOR`. Code
+/// that holds `MAPPER` or `FRAME_ALLOCATOR` must not allocate. Such an
+/// allocation panics here when the heap has to grow instead of deadlocking.
+fn grow(top: usize, size: usize, min_size: usize) -> Option<usize> {
+    let limit = heap_limit();
+    let grow_by = align_up(min_size.max(HEAP_GROW_STEP), 4096).min(limit.saturating_sub(size));
+    if size == 0 || grow_by < min_size {
+        return None;
     }
 
-    Ok(())
+    // 单核上锁被占用说明持有它的代码正在分配内存，等待会永远自旋
+    let (mut mapper, mut frame_allocator) =
+        match (memory::MAPPER.try_lock(), memory::FRAME_ALLOCATOR.try_lock()) {
+            (Some(mapper), Some(frame_allocator)) => (mapper, frame_allocator),
+            _ => panic!("heap allocation while memory::MAPPER or memory::FRAME_ALLOCATOR is held"),
+        };
+    match (mapper.as_mut(), frame_allocator.as_mut()) {
+        (Some(mapper), Some(frame_allocator)) => {
+            map_heap_range(top, grow_by, mapper, frame_allocator).ok()?;
+            Some(grow_by)
+        }
+        _ => None,
+    }
 }
 
+/// Align the given address `addr` upwards to alignment `align`.
+///
+/// Requires that `align` is a power of two.
+fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
+
+/// A wrapper around spin::Mutex to permit trait implementations.
+pub struct Locked<A> {
+    inner: spin::Mutex<A>,
+}
 
+impl<A> Locked<A> {
+    pub const fn new(inner: A) -> Self {
+        Locked {
+            inner: spin::Mutex::new(inner),
+        }
+    }
+
+    pub fn lock(&self) -> spin::MutexGuard<A> {
+        self.inner.lock()
+    }
+}
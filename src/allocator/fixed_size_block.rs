@@ -0,0 +1,97 @@
+use super::{linked_list::LinkedListAllocator, Locked};
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::{mem, ptr};
+
+/// The size classes of the allocator.
+///
+/// Every block is aligned to its size, so all sizes must be powers of two.
+const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+
+/// Returns the index of the smallest size class in `BLOCK_SIZES` that fits
+/// both the size and the alignment of the layout.
+fn list_index(layout: &Layout) -> Option<usize> {
+    let required_block_size = layout.size().max(layout.align());
+    BLOCK_SIZES.iter().position(|&s| s >= required_block_size)
+}
+
+struct ListNode {
+    next: Option<&'static mut ListNode>,
+}
+
+/// An allocator with one free list per size class in `BLOCK_SIZES`.
+///
+/// Freed blocks are pushed onto the list of their size class and handed out
+/// again by the next allocation of that class. Blocks are carved out of a
+/// `LinkedListAllocator`, which also serves all layouts larger than the biggest
+/// block size.
+pub struct FixedSizeBlockAllocator {
+    list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
+    fallback_allocator: LinkedListAllocator,
+}
+
+impl FixedSizeBlockAllocator {
+    /// Creates an empty FixedSizeBlockAllocator.
+    pub const fn new() -> Self {
+        const EMPTY: Option<&'static mut ListNode> = None;
+        FixedSizeBlockAllocator {
+            list_heads: [EMPTY; BLOCK_SIZES.len()],
+            fallback_allocator: LinkedListAllocator::new(),
+        }
+    }
+
+    /// Initializes the allocator with the given heap bounds.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that the given heap bounds are valid and that
+    /// the heap is unused. This method must be called only once.
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.fallback_allocator.init(heap_start, heap_size);
+    }
+
+    /// Returns the number of bytes managed by the allocator.
+    pub fn size(&self) -> usize {
+        self.fallback_allocator.size()
+    }
+}
+
+unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut allocator = self.lock();
+        match list_index(&layout) {
+            Some(index) => {
+                match allocator.list_heads[index].take() {
+                    Some(node) => {
+                        allocator.list_heads[index] = node.next.take();
+                        node as *mut ListNode as *mut u8
+                    }
+                    None => {
+                        // 空闲链表为空时从后备分配器切出一个按大小对齐的新块
+                        let block_size = BLOCK_SIZES[index];
+                        let layout = Layout::from_size_align(block_size, block_size).unwrap();
+                        allocator.fallback_allocator.allocate(layout)
+                    }
+                }
+            }
+            None => allocator.fallback_allocator.allocate(layout),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let mut allocator = self.lock();
+        match list_index(&layout) {
+            Some(index) => {
+                let new_node = ListNode {
+                    next: allocator.list_heads[index].take(),
+                };
+                // 空闲块中存放链表节点
+                assert!(mem::size_of::<ListNode>() <= BLOCK_SIZES[index]);
+                assert!(mem::align_of::<ListNode>() <= BLOCK_SIZES[index]);
+                let new_node_ptr = ptr as *mut ListNode;
+                ptr::write(new_node_ptr, new_node);
+                allocator.list_heads[index] = Some(&mut *new_node_ptr);
+            }
+            None => allocator.fallback_allocator.deallocate(ptr, layout),
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use super::{grow, Locked};
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::ptr::{null_mut, NonNull};
+use linked_list_allocator::Heap;
+
+/// A linked list heap that maps more of the heap window when an allocation
+/// does not fit.
+pub struct LinkedListAllocator {
+    heap: Heap,
+}
+
+impl LinkedListAllocator {
+    /// Creates an empty LinkedListAllocator.
+    pub const fn new() -> Self {
+        LinkedListAllocator {
+            heap: Heap::empty(),
+        }
+    }
+
+    /// Initializes the allocator with the given heap bounds.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that the given heap bounds are valid and that
+    /// the heap is unused. This method must be called only once.
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.heap.init(heap_start, heap_size);
+    }
+
+    /// Returns the number of bytes managed by the allocator.
+    pub fn size(&self) -> usize {
+        self.heap.size()
+    }
+
+    /// Allocates from the heap, growing it if the layout does not fit.
+    pub(super) fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        loop {
+            if let Ok(ptr) = self.heap.allocate_first_fit(layout) {
+                return ptr.as_ptr();
+            }
+            // 新的空间可能与末尾的空闲块合并，多申请一个对齐量以保证能容纳该布局
+            let min_size = layout.size() + layout.align();
+            match grow(self.heap.top(), self.heap.size(), min_size) {
+                Some(grow_by) => unsafe { self.heap.extend(grow_by) },
+                None => return null_mut(),
+            }
+        }
+    }
+
+    /// Returns the given block to the heap.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that `ptr` was allocated by this allocator
+    /// with the same layout.
+    pub(super) unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        self.heap.deallocate(NonNull::new_unchecked(ptr), layout);
+    }
+}
+
+unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.lock().allocate(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.lock().deallocate(ptr, layout)
+    }
+}
@@ -0,0 +1,127 @@
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::ops::Deref;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::serial_println;
+
+/// Number of buckets in the allocation size histogram.
+///
+/// Bucket `i` counts allocations of at most `8 << i` bytes, the last bucket
+/// counts all larger allocations.
+pub const HISTOGRAM_BUCKETS: usize = 16;
+
+// only used to initialize the histogram array below
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicUsize = AtomicUsize::new(0);
+
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+static PEAK_BYTES: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static DEALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static HISTOGRAM: [AtomicUsize; HISTOGRAM_BUCKETS] = [ZERO; HISTOGRAM_BUCKETS];
+
+/// A snapshot of the heap usage, returned by `stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapStats {
+    /// Bytes currently allocated.
+    pub live_bytes: usize,
+    /// The highest value `live_bytes` ever reached.
+    pub peak_bytes: usize,
+    /// Number of successful allocations since boot.
+    pub allocations: usize,
+    /// Number of deallocations since boot.
+    pub deallocations: usize,
+    /// Number of allocations per size class, see `HISTOGRAM_BUCKETS`.
+    pub histogram: [usize; HISTOGRAM_BUCKETS],
+}
+
+impl HeapStats {
+    /// Returns the number of allocations that have not been freed yet.
+    pub fn live_allocations(&self) -> usize {
+        self.allocations - self.deallocations
+    }
+
+    /// Returns the largest size counted in the given histogram bucket, or
+    /// `None` for the last bucket, which has no upper bound.
+    pub fn bucket_limit(bucket: usize) -> Option<usize> {
+        if bucket + 1 < HISTOGRAM_BUCKETS {
+            Some(8 << bucket)
+        } else {
+            None
+        }
+    }
+}
+
+/// Returns the histogram bucket for an allocation of `size` bytes.
+pub fn bucket_index(size: usize) -> usize {
+    (0..HISTOGRAM_BUCKETS - 1)
+        .find(|&bucket| size <= 8 << bucket)
+        .unwrap_or(HISTOGRAM_BUCKETS - 1)
+}
+
+/// Returns the current heap statistics.
+pub fn stats() -> HeapStats {
+    let mut histogram = [0; HISTOGRAM_BUCKETS];
+    for (count, bucket) in histogram.iter_mut().zip(HISTOGRAM.iter()) {
+        *count = bucket.load(Ordering::Relaxed);
+    }
+    HeapStats {
+        live_bytes: LIVE_BYTES.load(Ordering::Relaxed),
+        peak_bytes: PEAK_BYTES.load(Ordering::Relaxed),
+        allocations: ALLOCATIONS.load(Ordering::Relaxed),
+        deallocations: DEALLOCATIONS.load(Ordering::Relaxed),
+        histogram,
+    }
+}
+
+/// Prints the allocations made since `since` that are still live to the
+/// serial port. Prints nothing if there are none.
+pub fn report_leaks(since: &HeapStats) {
+    let now = stats();
+    let leaked = now.live_allocations() as isize - since.live_allocations() as isize;
+    if leaked > 0 {
+        serial_println!(
+            "[leak] {} allocation(s) with {} bytes still live",
+            leaked,
+            now.live_bytes as isize - since.live_bytes as isize
+        );
+    }
+}
+
+/// A wrapper around a `GlobalAlloc` that records every allocation in the
+/// statistics returned by `stats`.
+pub struct Tracked<A> {
+    inner: A,
+}
+
+impl<A> Tracked<A> {
+    pub const fn new(inner: A) -> Self {
+        Tracked { inner }
+    }
+}
+
+impl<A> Deref for Tracked<A> {
+    type Target = A;
+
+    fn deref(&self) -> &A {
+        &self.inner
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for Tracked<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc(layout);
+        if !ptr.is_null() {
+            let live = LIVE_BYTES.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK_BYTES.fetch_max(live, Ordering::Relaxed);
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            HISTOGRAM[bucket_index(layout.size())].fetch_add(1, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.dealloc(ptr, layout);
+        LIVE_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+        DEALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    }
+}
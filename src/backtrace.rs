@@ -0,0 +1,207 @@
+use core::fmt;
+use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::VirtAddr;
+use crate::memory;
+
+/// Size of the `.ksyms` section, must match the `.zero` directive below.
+const KSYMS_SIZE: usize = 0x10_0000;
+
+const MAGIC: &[u8; 8] = b"KSYMTAB1";
+const HEADER_SIZE: usize = 16;
+const ENTRY_SIZE: usize = 24;
+
+/// Maximum number of frames printed by a backtrace.
+const MAX_FRAMES: usize = 64;
+
+// 为符号表预留的空间，链接后由`tools/ksyms`写入内核的函数符号
+global_asm!(
+    r#"
+    .section .ksyms, "a", @progbits
+    .balign 8
+    .global __ksyms
+__ksyms:
+    .zero 0x100000
+    .previous
+"#
+);
+
+extern "C" {
+    static __ksyms: [u8; KSYMS_SIZE];
+}
+
+fn table() -> Option<&'static [u8]> {
+    let table = unsafe { &__ksyms };
+    if &table[..8] == MAGIC {
+        Some(table)
+    } else {
+        None
+    }
+}
+
+fn read_u64(table: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&table[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+fn read_u32(table: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&table[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+/// Returns whether `tools/ksyms` has written the symbol table into the kernel.
+pub fn has_symbols() -> bool {
+    table().is_some()
+}
+
+/// Returns whether the kernel was built to be run through `ksyms --run`, i.e.
+/// whether `CARGO_TARGET_X86_64_OS_626_RUNNER` selected the `ksyms` runner
+/// when the kernel was compiled. The symbol table must be present then.
+pub fn symbols_expected() -> bool {
+    option_env!("CARGO_TARGET_X86_64_OS_626_RUNNER").map_or(false, |runner| runner.contains("ksyms"))
+}
+
+/// Returns the name of the function that contains `addr` and the offset of
+/// `addr` from the start of that function.
+pub fn symbolize(addr: VirtAddr) -> Option<(&'static str, u64)> {
+    let table = table()?;
+    let addr = addr.as_u64();
+    let count = read_u64(table, 8) as usize;
+    let entry = |index: usize| HEADER_SIZE + index * ENTRY_SIZE;
+
+    // 二分查找最后一个起始地址不大于`addr`的函数
+    let (mut low, mut high) = (0, count);
+    while low < high {
+        let mid = (low + high) / 2;
+        if read_u64(table, entry(mid)) <= addr {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    let offset = entry(low.checked_sub(1)?);
+    let start = read_u64(table, offset);
+    let size = read_u64(table, offset + 8);
+    if addr >= start + size {
+        return None;
+    }
+    let name_offset = read_u32(table, offset + 16) as usize;
+    let name_len = read_u32(table, offset + 20) as usize;
+    let name = table.get(name_offset..name_offset + name_len)?;
+    Some((core::str::from_utf8(name).ok()?, addr - start))
+}
+
+/// Returns whether the 16 byte frame record at `rbp` can be read.
+fn is_frame_record(rbp: u64) -> bool {
+    if rbp == 0 || rbp % 8 != 0 || memory::physical_memory_offset().as_u64() == 0 {
+        return false;
+    }
+    let offset = memory::physical_memory_offset();
+    [rbp, rbp + 8].iter().all(|&addr| {
+        VirtAddr::try_new(addr)
+            .ok()
+            .and_then(|addr| unsafe { memory::translate_addr(addr, offset) })
+            .is_some()
+    })
+}
+
+/// Follows the chain of frame pointers starting at `rbp` and calls `f` with
+/// the return address of every frame.
+///
+/// The walk stops at a frame pointer that is not mapped or does not point
+/// further up the stack, and, if the kernel has a symbol table, at a return
+/// address outside of any function, e.g. at the frame of an interrupt.
+pub fn walk<F: FnMut(VirtAddr)>(mut rbp: u64, mut f: F) {
+    let symbols = has_symbols();
+    for _ in 0..MAX_FRAMES {
+        if !is_frame_record(rbp) {
+            return;
+        }
+        let (next, return_address) = unsafe { (*(rbp as *const u64), *((rbp + 8) as *const u64)) };
+        let return_address = match VirtAddr::try_new(return_address) {
+            Ok(addr) if addr.as_u64() != 0 => addr,
+            _ => return,
+        };
+        if symbols && symbolize(return_address - 1u64).is_none() {
+            return;
+        }
+        f(return_address);
+        if next <= rbp {
+            return;
+        }
+        rbp = next;
+    }
+}
+
+/// Returns the frame pointer of the caller.
+#[inline(always)]
+pub fn frame_pointer() -> u64 {
+    let rbp: u64;
+    unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags)) };
+    rbp
+}
+
+fn emit(args: fmt::Arguments) {
+    crate::vga_buffer::_print(args);
+    crate::serial::_print(args);
+}
+
+fn print_frame(index: usize, addr: VirtAddr, return_address: bool) {
+    // 返回地址可能已经在函数之外（例如调用不返回的函数），用前一个字节查找符号
+    let symbol = if return_address {
+        symbolize(addr - 1u64).map(|(name, offset)| (name, offset + 1))
+    } else {
+        symbolize(addr)
+    };
+    match symbol {
+        Some((name, offset)) => emit(format_args!("  {:>2}: {:#x} {}+{:#x}\n", index, addr.as_u64(), name, offset)),
+        None => emit(format_args!("  {:>2}: {:#x} <unknown>\n", index, addr.as_u64())),
+    }
+}
+
+/// Prints the backtrace of the code that was interrupted at `rip` with the
+/// frame pointer `rbp`, e.g. as saved in a `TrapFrame`.
+pub fn print_context(rip: VirtAddr, rbp: u64) {
+    print_from(Some(rip), rbp);
+}
+
+fn print_from(rip: Option<VirtAddr>, rbp: u64) {
+    emit(format_args!("backtrace:\n"));
+    if !has_symbols() {
+        emit(format_args!("  (no symbol table, run `ksyms` on the kernel)\n"));
+    }
+    let mut index = 0;
+    if let Some(rip) = rip {
+        // 出错的指令本身不是返回地址
+        print_frame(index, rip, false);
+        index += 1;
+    }
+    walk(rbp, |addr| {
+        print_frame(index, addr, true);
+        index += 1;
+    });
+}
+
+/// Prints the backtrace of the caller to the VGA text buffer and the serial port.
+#[inline(never)]
+pub fn print() {
+    print_from(None, frame_pointer());
+}
+
+/// Prints the backtrace of the code that was interrupted by an exception.
+///
+/// Must be called directly from the exception handler, because the frame
+/// pointer of the interrupted code is found through the handler's frame.
+#[inline(never)]
+pub fn print_interrupted(stack_frame: &InterruptStackFrame) {
+    let rbp = frame_pointer();
+    // 本函数的栈帧 -> 异常处理函数的栈帧 -> 被中断代码的栈帧
+    let handler_rbp = if is_frame_record(rbp) { unsafe { *(rbp as *const u64) } } else { 0 };
+    let interrupted_rbp = if is_frame_record(handler_rbp) {
+        unsafe { *(handler_rbp as *const u64) }
+    } else {
+        0
+    };
+    print_from(Some(stack_frame.instruction_pointer), interrupted_rbp);
+}
@@ -0,0 +1,75 @@
+use core::arch::x86_64::{__cpuid, __cpuid_count};
+use x86_64::registers::control::{Cr4, Cr4Flags};
+
+/// The CPU features the kernel makes use of, detected with CPUID.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Features {
+    /// A local APIC, see `interrupts::enable_apic`.
+    pub apic: bool,
+    /// Page attribute table, see `memory::CacheMode::WriteCombining`.
+    pub pat: bool,
+    /// Process-context identifiers, see `memory::AddressSpace::activate`.
+    pub pcid: bool,
+    /// The TSC-deadline mode of the local APIC timer, see `time::TimerMode`.
+    pub tsc_deadline: bool,
+    /// Supervisor mode execution prevention.
+    pub smep: bool,
+    /// Supervisor mode access prevention.
+    pub smap: bool,
+    /// User mode instruction prevention.
+    pub umip: bool,
+}
+
+/// Detects the supported CPU features.
+pub fn features() -> Features {
+    let max_leaf = unsafe { __cpuid(0) }.eax;
+    let leaf_1 = unsafe { __cpuid(1) };
+    let mut features = Features {
+        apic: leaf_1.edx & (1 << 9) != 0,
+        pat: leaf_1.edx & (1 << 16) != 0,
+        pcid: leaf_1.ecx & (1 << 17) != 0,
+        tsc_deadline: leaf_1.ecx & (1 << 24) != 0,
+        ..Features::default()
+    };
+    // 结构化扩展特性位于CPUID.07H，子叶0
+    if max_leaf >= 7 {
+        let leaf_7 = unsafe { __cpuid_count(7, 0) };
+        features.smep = leaf_7.ebx & (1 << 7) != 0;
+        features.smap = leaf_7.ebx & (1 << 20) != 0;
+        features.umip = leaf_7.ecx & (1 << 2) != 0;
+    }
+    features
+}
+
+/// Enables SMEP, SMAP and UMIP in CR4 if the CPU supports them and returns
+/// the detected features.
+///
+/// With SMAP enabled the kernel can only access user pages between `stac`
+/// and `clac`, see `memory::user::copy_from_user`.
+pub fn enable_security_features() -> Features {
+    let features = features();
+    let mut flags = Cr4Flags::empty();
+    flags.set(Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION, features.smep);
+    flags.set(Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION, features.smap);
+    flags.set(Cr4Flags::USER_MODE_INSTRUCTION_PREVENTION, features.umip);
+    unsafe { Cr4::update(|cr4| cr4.insert(flags)) };
+    features
+}
+
+/// Enables process-context identifiers if the CPU supports them and returns
+/// whether they are enabled.
+///
+/// Must be called while the PCID in CR3 is 0, i.e. before switching to
+/// another address space.
+pub fn enable_pcid() -> bool {
+    if !features().pcid {
+        return false;
+    }
+    unsafe { Cr4::update(|cr4| cr4.insert(Cr4Flags::PCID)) };
+    true
+}
+
+/// Returns whether SMAP is enabled in CR4.
+pub fn smap_enabled() -> bool {
+    Cr4::read().contains(Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION)
+}
@@ -1,30 +1,42 @@
+use core::cell::UnsafeCell;
 use x86_64::VirtAddr;
+use crate::memory::{self, stack::StackError};
 use x86_64::structures::tss::TaskStateSegment;
 use lazy_static::lazy_static;
 use x86_64::structures::gdt::{GlobalDescriptorTable, Descriptor, SegmentSelector};
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 
-lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
-            let stack_end = stack_start + STACK_SIZE;
-            stack_end
-        };
-        tss
-    };
+/// Number of pages of the double fault stack allocated by `init_stacks`.
+pub const DOUBLE_FAULT_STACK_PAGES: u64 = 5;
+
+// 内存初始化之前使用的双重异常栈，没有保护页，由`init_stacks`替换
+const BOOT_STACK_SIZE: usize = 4096 * 5;
+static mut BOOT_STACK: [u8; BOOT_STACK_SIZE] = [0; BOOT_STACK_SIZE];
+
+/// The task state segment, behind an `UnsafeCell` so that `init_stacks` can
+/// replace a stack of the interrupt stack table after the GDT refers to it.
+struct Tss(UnsafeCell<TaskStateSegment>);
+
+// 中断栈表只在初始化时通过裸指针写入，CPU在中断时直接读取内存
+unsafe impl Sync for Tss {}
+
+impl Tss {
+    /// Sets the stack the CPU switches to for the given interrupt stack table index.
+    fn set_interrupt_stack(&self, index: u16, top: VirtAddr) {
+        // 不创建对TSS的引用，描述符中只保存它的地址
+        unsafe { (*self.0.get()).interrupt_stack_table[index as usize] = top };
+    }
 }
 
+static TSS: Tss = Tss(UnsafeCell::new(TaskStateSegment::new()));
+
 lazy_static! {
     static ref GDT: (GlobalDescriptorTable, Selectors) = {
         let mut gdt = GlobalDescriptorTable::new();
         let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
+        // 这个引用只用来取得TSS的地址，之后不再使用
+        let tss_selector = gdt.add_entry(Descriptor::tss_segment(unsafe { &*TSS.0.get() }));
         (gdt, Selectors { code_selector, tss_selector}, )
     };
 }
@@ -38,6 +50,8 @@ pub fn init() {
     use x86_64::instructions::segmentation::set_cs;
     use x86_64::instructions::tables::load_tss;
 
+    let stack_start = VirtAddr::from_ptr(unsafe { &BOOT_STACK });
+    TSS.set_interrupt_stack(DOUBLE_FAULT_IST_INDEX, stack_start + BOOT_STACK_SIZE);
     GDT.0.load();
     unsafe {
         set_cs(GDT.1.code_selector);
@@ -45,4 +59,13 @@ pub fn init() {
     }
 }
 
-
+/// Replaces the boot double fault stack by a stack with a guard page, so that
+/// an overflow of the double fault handler is reported instead of silently
+/// corrupting memory.
+///
+/// Requires `memory::init_global`.
+pub fn init_stacks() -> Result<(), StackError> {
+    let stack = memory::stack::allocate_stack("double fault", DOUBLE_FAULT_STACK_PAGES)?;
+    TSS.set_interrupt_stack(DOUBLE_FAULT_IST_INDEX, stack.top);
+    Ok(())
+}
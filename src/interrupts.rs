@@ -1,9 +1,23 @@
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
-use crate::{ gdt, println, print, hlt_loop };
+use crate::{ backtrace, gdt, memory, println, print };
+use crate::memory::fault::PageFault;
 use lazy_static::lazy_static;
 use pic8259_simple::ChainedPics;
 use spin;
 
+pub mod apic;
+mod exceptions;
+mod irq;
+mod trap;
+
+pub use self::apic::ApicError;
+pub use self::irq::{
+    apic_enabled, enable_apic, irq_count, is_masked, mask_irq, register_irq, spurious_count, unmask_irq,
+    unregister_irq, IrqError, IrqHandler, IRQ_COUNT,
+};
+pub use self::exceptions::{has_error_code, vector_name, CrashReport, Registers, SelectorErrorCode};
+pub use self::trap::{set_trap_stub, set_trap_stub_with_error_code, TrapFrame, TrapStub};
+
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 
@@ -14,14 +28,15 @@ pub static PICS: spin::Mutex<ChainedPics> = spin::Mutex::new(
 lazy_static!{
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
+        exceptions::set_handlers(&mut idt);
+        set_trap_stub(&mut idt.breakpoint, breakpoint_entry);
         unsafe{
             idt.double_fault
                 .set_handler_fn(double_fault_handler)
                 .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
         }
-        idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
-        idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
+        irq::set_handlers(&mut idt);
+        apic::set_handlers(&mut idt);
         idt.page_fault.set_handler_fn(page_fault_handler);
         idt
     };
@@ -33,42 +48,66 @@ pub fn init_idt(){
     IDT.load();
 }
 
-extern "x86-interrupt" fn breakpoint_handler(stack_frame: &mut InterruptStackFrame){
-    println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
-}
+crate::trap_entry!(breakpoint_entry, 3, breakpoint_handler);
 
-extern "x86-interrupt" fn double_fault_handler(stack_frame: &mut InterruptStackFrame, _error_code: u64) -> !{
-    panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
+extern "C" fn breakpoint_handler(frame: &mut TrapFrame){
+    println!("EXCEPTION: BREAKPOINT\n{:#?}", frame);
 }
 
-extern "x86-interrupt" fn timer_interrupt_handler(
-    _stack_frame: &mut InterruptStackFrame)
-{
-    print!(".");
+extern "x86-interrupt" fn double_fault_handler(stack_frame: &mut InterruptStackFrame, error_code: u64) -> !{
+    use x86_64::registers::control::Cr2;
 
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
+    let report = CrashReport::new(8, Some(error_code), stack_frame);
+    backtrace::print_interrupted(stack_frame);
+    // 栈溢出时CPU无法压入缺页异常的栈帧，会直接触发双重异常，CR2中仍是保护页的地址
+    if let Some(stack) = memory::fault::overflowed_stack(Cr2::read()) {
+        panic!("{}", report.with_cause(&format_args!("stack overflow on stack {}", stack)));
     }
+    panic!("{}", report);
+}
+
+/// Initializes the PICs and registers the timer and keyboard handlers.
+pub fn init_irqs(){
+    irq::init_pics();
+    register_irq(InterruptIndex::Timer.as_irq(), timer_interrupt_handler)
+        .expect("registering the timer handler failed");
+    register_irq(InterruptIndex::Keyboard.as_irq(), keyboard_interrupt_handler)
+        .expect("registering the keyboard handler failed");
+}
+
+fn timer_interrupt_handler(_frame: &mut TrapFrame)
+{
+    crate::time::tick();
+    crate::time::timer::on_tick();
 }
 
 
 
 extern "x86-interrupt" fn page_fault_handler(
-    _stack_frame: &mut InterruptStackFrame,
-    _error_code: PageFaultErrorCode,
+    stack_frame: &mut InterruptStackFrame,
+    error_code: PageFaultErrorCode,
 ){
     use x86_64::registers::control::Cr2;
-    println!("EXCEPTION: PAGE FAULT");
-    println!("Accessed Address: {:?}", Cr2::read());
-    println!("Error Code: {:?}", _error_code);
-    println!("{:#?}", _stack_frame);
-    hlt_loop();
+
+    let fault = PageFault {
+        addr: Cr2::read(),
+        error_code,
+        instruction_pointer: stack_frame.instruction_pointer,
+    };
+    // 由注册了该地址的内存区域处理缺页，无法处理时才视为非法访问
+    if let Err(error) = memory::fault::dispatch(&fault) {
+        // 拷贝用户内存时的缺页不是内核错误，跳转到拷贝函数的错误返回路径
+        if let Some(fixup) = memory::user::fixup_address(fault.instruction_pointer, fault.addr) {
+            unsafe { stack_frame.as_mut().instruction_pointer = fixup };
+            return;
+        }
+        let report = CrashReport::new(14, Some(error_code.bits()), stack_frame);
+        backtrace::print_interrupted(stack_frame);
+        panic!("{}", report.with_cause(&format_args!("{}\n{}", error, fault)));
+    }
 }
 
-extern "x86-interrupt" fn keyboard_interrupt_handler(
-    _stack_frame: &mut InterruptStackFrame
-){
+fn keyboard_interrupt_handler(_frame: &mut TrapFrame){
     use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
     use spin::Mutex;
     use x86_64::instructions::port::Port;
@@ -92,11 +131,6 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(
             }
         }
     }
-
-
-    unsafe {
-        PICS.lock().notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
-    }
 }
 
 #[cfg(test)]
@@ -114,13 +148,15 @@ fn test_breakpoint_exception() {
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     Keyboard,
+    Rtc = PIC_2_OFFSET,
 }
 
 impl InterruptIndex {
     fn as_u8(self) -> u8 {
         self as u8
     }
-    fn as_usize(self) -> usize {
-        usize::from(self.as_u8())
+    /// The line of the PIC that raises the interrupt.
+    pub fn as_irq(self) -> u8 {
+        self.as_u8() - PIC_1_OFFSET
     }
 }
@@ -0,0 +1,325 @@
+use core::fmt;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use spin::Mutex;
+use x86_64::registers::model_specific::Msr;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+use x86_64::PhysAddr;
+use crate::cpu;
+use crate::memory::{self, vm::VmError, Mmio};
+use super::trap::{set_trap_stub, TrapFrame};
+use super::{IrqHandler, PIC_2_OFFSET};
+
+/// The model specific register with the physical address of the local APIC.
+const IA32_APIC_BASE: u32 = 0x1b;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+const APIC_BASE_ADDRESS_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+/// The usual physical address of the first I/O APIC.
+const IO_APIC_BASE: u64 = 0xfec0_0000;
+
+// 本地APIC寄存器的偏移
+const LAPIC_ID: usize = 0x20;
+const LAPIC_TASK_PRIORITY: usize = 0x80;
+const LAPIC_EOI: usize = 0xb0;
+const LAPIC_SPURIOUS: usize = 0xf0;
+const LAPIC_LVT_TIMER: usize = 0x320;
+const LAPIC_LVT_LINT0: usize = 0x350;
+const LAPIC_LVT_ERROR: usize = 0x370;
+const LAPIC_TIMER_INITIAL_COUNT: usize = 0x380;
+const LAPIC_TIMER_CURRENT_COUNT: usize = 0x390;
+const LAPIC_TIMER_DIVIDE: usize = 0x3e0;
+const LAPIC_SOFTWARE_ENABLE: u32 = 1 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_TIMER_PERIODIC: u32 = 0b01 << 17;
+const LVT_TIMER_TSC_DEADLINE: u32 = 0b10 << 17;
+/// Divide configuration for dividing the bus clock by 16.
+const TIMER_DIVIDE_BY_16: u32 = 0b0011;
+/// The model specific register holding the deadline in TSC-deadline mode.
+const IA32_TSC_DEADLINE: u32 = 0x6e0;
+
+// I/O APIC通过选择寄存器和数据窗口间接访问
+const IO_APIC_SELECT: usize = 0x00;
+const IO_APIC_WINDOW: usize = 0x10;
+const IO_APIC_VERSION: u32 = 0x01;
+const IO_APIC_REDIRECTION_TABLE: u32 = 0x10;
+const REDIRECTION_MASKED: u64 = 1 << 16;
+
+/// The vector of spurious interrupts of the local APIC, which need no EOI.
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+
+/// The vector of the local APIC timer, the first one after the legacy IRQs.
+pub const TIMER_VECTOR: u8 = PIC_2_OFFSET + 8;
+
+/// The operating mode of the local APIC timer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerMode {
+    /// One interrupt after the initial count reached zero.
+    OneShot,
+    /// An interrupt each time the count reaches zero, the count restarts
+    /// from the initial count.
+    Periodic,
+    /// One interrupt when the TSC reaches the value written to the
+    /// `IA32_TSC_DEADLINE` MSR.
+    TscDeadline,
+}
+
+/// The error returned by `enable_apic`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApicError {
+    /// The kernel was built with the `legacy-pic` feature.
+    Disabled,
+    /// CPUID reports no local APIC.
+    NotSupported,
+    /// No I/O APIC responds at its usual address.
+    NoIoApic,
+    /// Mapping the registers failed.
+    Map(VmError),
+}
+
+impl fmt::Display for ApicError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ApicError::Disabled => write!(f, "disabled by the legacy-pic feature"),
+            ApicError::NotSupported => write!(f, "the cpu has no local apic"),
+            ApicError::NoIoApic => write!(f, "no i/o apic found"),
+            ApicError::Map(error) => write!(f, "mapping the apic registers failed: {}", error),
+        }
+    }
+}
+
+impl From<VmError> for ApicError {
+    fn from(error: VmError) -> Self {
+        ApicError::Map(error)
+    }
+}
+
+/// The local APIC of the current CPU.
+pub struct LocalApic {
+    mmio: Mmio,
+}
+
+impl LocalApic {
+    pub fn read(&self, register: usize) -> u32 {
+        self.mmio.read(register)
+    }
+
+    pub fn write(&mut self, register: usize, value: u32) {
+        self.mmio.write(register, value)
+    }
+
+    /// Returns the APIC id of the current CPU.
+    pub fn id(&self) -> u8 {
+        (self.read(LAPIC_ID) >> 24) as u8
+    }
+
+    /// Signals the end of the interrupt that is in service.
+    pub fn end_of_interrupt(&mut self) {
+        self.write(LAPIC_EOI, 0);
+    }
+
+    /// Starts the timer with the bus clock divided by 16. `initial_count` is
+    /// ignored in `TimerMode::TscDeadline`, use `set_tsc_deadline` instead.
+    pub fn start_timer(&mut self, mode: TimerMode, initial_count: u32, masked: bool) {
+        let mut lvt = u32::from(TIMER_VECTOR);
+        match mode {
+            TimerMode::OneShot => {}
+            TimerMode::Periodic => lvt |= LVT_TIMER_PERIODIC,
+            TimerMode::TscDeadline => lvt |= LVT_TIMER_TSC_DEADLINE,
+        }
+        if masked {
+            lvt |= LVT_MASKED;
+        }
+        self.write(LAPIC_TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+        self.write(LAPIC_LVT_TIMER, lvt);
+        if mode != TimerMode::TscDeadline {
+            self.write(LAPIC_TIMER_INITIAL_COUNT, initial_count);
+        }
+    }
+
+    /// Arms the timer in `TimerMode::TscDeadline` to fire when the TSC
+    /// reaches `tsc`.
+    pub fn set_tsc_deadline(&mut self, tsc: u64) {
+        unsafe { Msr::new(IA32_TSC_DEADLINE).write(tsc) };
+    }
+
+    /// Returns the current count of the timer.
+    pub fn timer_count(&self) -> u32 {
+        self.read(LAPIC_TIMER_CURRENT_COUNT)
+    }
+
+    /// Stops the timer and masks its interrupt.
+    pub fn stop_timer(&mut self) {
+        self.write(LAPIC_LVT_TIMER, LVT_MASKED | u32::from(TIMER_VECTOR));
+        self.write(LAPIC_TIMER_INITIAL_COUNT, 0);
+        if cpu::features().tsc_deadline {
+            self.set_tsc_deadline(0);
+        }
+    }
+}
+
+/// An I/O APIC, which routes device interrupts to local APICs.
+pub struct IoApic {
+    mmio: Mmio,
+    pins: u8,
+}
+
+impl IoApic {
+    fn read(&mut self, register: u32) -> u32 {
+        self.mmio.write(IO_APIC_SELECT, register);
+        self.mmio.read(IO_APIC_WINDOW)
+    }
+
+    fn write(&mut self, register: u32, value: u32) {
+        self.mmio.write(IO_APIC_SELECT, register);
+        self.mmio.write(IO_APIC_WINDOW, value);
+    }
+
+    /// Returns the number of interrupt pins.
+    pub fn pins(&self) -> u8 {
+        self.pins
+    }
+
+    fn redirection(&mut self, pin: u8) -> u64 {
+        let register = IO_APIC_REDIRECTION_TABLE + 2 * u32::from(pin);
+        u64::from(self.read(register)) | u64::from(self.read(register + 1)) << 32
+    }
+
+    fn set_redirection(&mut self, pin: u8, entry: u64) {
+        let register = IO_APIC_REDIRECTION_TABLE + 2 * u32::from(pin);
+        // 先写入低32位中的屏蔽位会在写完高32位之前生效，所以先写高32位
+        self.write(register + 1, (entry >> 32) as u32);
+        self.write(register, entry as u32);
+    }
+
+    /// Routes `pin` to `vector` on the local APIC `apic_id` as edge triggered,
+    /// active high interrupt, which is how ISA interrupts are signaled.
+    pub fn route(&mut self, pin: u8, vector: u8, apic_id: u8, masked: bool) {
+        let mut entry = u64::from(vector) | u64::from(apic_id) << 56;
+        if masked {
+            entry |= REDIRECTION_MASKED;
+        }
+        self.set_redirection(pin, entry);
+    }
+
+    pub fn set_masked(&mut self, pin: u8, masked: bool) {
+        let entry = self.redirection(pin);
+        if masked {
+            self.set_redirection(pin, entry | REDIRECTION_MASKED);
+        } else {
+            self.set_redirection(pin, entry & !REDIRECTION_MASKED);
+        }
+    }
+
+    pub fn is_masked(&mut self, pin: u8) -> bool {
+        self.redirection(pin) & REDIRECTION_MASKED != 0
+    }
+}
+
+/// The local APIC, available after `interrupts::enable_apic`.
+///
+/// The IRQ dispatcher locks it for the EOI, so it must only be locked with
+/// interrupts disabled. The same holds for `IO_APIC`.
+pub static LOCAL_APIC: Mutex<Option<LocalApic>> = Mutex::new(None);
+
+/// The I/O APIC, available after `interrupts::enable_apic`.
+pub static IO_APIC: Mutex<Option<IoApic>> = Mutex::new(None);
+
+/// Returns the I/O APIC pin of the legacy IRQ line `irq`.
+///
+/// The PIT is connected to pin 2 instead of pin 0, as the interrupt source
+/// override in the ACPI tables of QEMU and most PCs says.
+pub fn io_apic_pin(irq: u8) -> u8 {
+    if irq == 0 {
+        2
+    } else {
+        irq
+    }
+}
+
+/// Maps and enables the local APIC and the I/O APIC. All I/O APIC pins are
+/// masked afterwards.
+pub(super) fn init() -> Result<(), ApicError> {
+    if cfg!(feature = "legacy-pic") {
+        return Err(ApicError::Disabled);
+    }
+    if !cpu::features().apic {
+        return Err(ApicError::NotSupported);
+    }
+    let mut base_msr = Msr::new(IA32_APIC_BASE);
+    let base = unsafe { base_msr.read() };
+
+    // 两个映射都成功之后才启用本地APIC，失败时内核继续使用8259
+    let mut local = LocalApic { mmio: memory::map_mmio(PhysAddr::new(base & APIC_BASE_ADDRESS_MASK), 4096)? };
+    let mmio = match memory::map_mmio(PhysAddr::new(IO_APIC_BASE), 4096) {
+        Ok(mmio) => mmio,
+        Err(error) => {
+            let _ = local.mmio.unmap();
+            return Err(error.into());
+        }
+    };
+    let mut io = IoApic { mmio, pins: 0 };
+    let version = io.read(IO_APIC_VERSION);
+    if version == u32::MAX {
+        let _ = io.mmio.unmap();
+        let _ = local.mmio.unmap();
+        return Err(ApicError::NoIoApic);
+    }
+    io.pins = ((version >> 16) & 0xff) as u8 + 1;
+    for pin in 0..io.pins {
+        io.set_masked(pin, true);
+    }
+    unsafe { base_msr.write(base | APIC_BASE_ENABLE) };
+
+    // 屏蔽LINT0上的8259虚拟线模式和本地APIC定时器，然后启用本地APIC
+    local.write(LAPIC_LVT_TIMER, LVT_MASKED);
+    local.write(LAPIC_LVT_LINT0, LVT_MASKED);
+    local.write(LAPIC_LVT_ERROR, LVT_MASKED);
+    local.write(LAPIC_TASK_PRIORITY, 0);
+    local.write(LAPIC_SPURIOUS, LAPIC_SOFTWARE_ENABLE | u32::from(SPURIOUS_VECTOR));
+
+    *LOCAL_APIC.lock() = Some(local);
+    *IO_APIC.lock() = Some(io);
+    Ok(())
+}
+
+/// Signals the end of an interrupt to the local APIC.
+pub(super) fn end_of_interrupt() {
+    if let Some(local) = LOCAL_APIC.lock().as_mut() {
+        local.end_of_interrupt();
+    }
+}
+
+static TIMER_INTERRUPTS: AtomicU64 = AtomicU64::new(0);
+static TIMER_HANDLER: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the number of local APIC timer interrupts.
+pub fn timer_interrupts() -> u64 {
+    TIMER_INTERRUPTS.load(Ordering::SeqCst)
+}
+
+/// Sets the function called on every local APIC timer interrupt, or removes
+/// it. As with `register_irq`, the EOI is sent after the handler returns.
+pub fn set_timer_handler(handler: Option<IrqHandler>) {
+    TIMER_HANDLER.store(handler.map_or(0, |handler| handler as usize), Ordering::SeqCst);
+}
+
+extern "C" fn timer_interrupt_handler(frame: &mut TrapFrame) {
+    TIMER_INTERRUPTS.fetch_add(1, Ordering::SeqCst);
+    let handler = TIMER_HANDLER.load(Ordering::SeqCst);
+    if handler != 0 {
+        let handler: IrqHandler = unsafe { core::mem::transmute(handler) };
+        handler(frame);
+    }
+    end_of_interrupt();
+}
+
+crate::trap_entry!(timer_entry, TIMER_VECTOR, timer_interrupt_handler);
+
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: &mut InterruptStackFrame) {
+    // 伪中断不需要EOI
+}
+
+pub(super) fn set_handlers(idt: &mut InterruptDescriptorTable) {
+    set_trap_stub(&mut idt[usize::from(TIMER_VECTOR)], timer_entry);
+    idt[usize::from(SPURIOUS_VECTOR)].set_handler_fn(spurious_interrupt_handler);
+}
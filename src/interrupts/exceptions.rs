@@ -0,0 +1,241 @@
+use core::fmt;
+use x86_64::registers::control::{Cr0, Cr2, Cr3, Cr4};
+use x86_64::structures::idt::{
+    InterruptDescriptorTable, InterruptStackFrame, InterruptStackFrameValue, PageFaultErrorCode,
+};
+use crate::backtrace;
+use super::trap::{set_trap_stub, set_trap_stub_with_error_code, TrapFrame};
+
+/// Returns the mnemonic and the name of an exception vector.
+pub fn vector_name(vector: u8) -> (&'static str, &'static str) {
+    match vector {
+        0 => ("#DE", "DIVIDE ERROR"),
+        1 => ("#DB", "DEBUG"),
+        2 => ("NMI", "NON-MASKABLE INTERRUPT"),
+        3 => ("#BP", "BREAKPOINT"),
+        4 => ("#OF", "OVERFLOW"),
+        5 => ("#BR", "BOUND RANGE EXCEEDED"),
+        6 => ("#UD", "INVALID OPCODE"),
+        7 => ("#NM", "DEVICE NOT AVAILABLE"),
+        8 => ("#DF", "DOUBLE FAULT"),
+        10 => ("#TS", "INVALID TSS"),
+        11 => ("#NP", "SEGMENT NOT PRESENT"),
+        12 => ("#SS", "STACK SEGMENT FAULT"),
+        13 => ("#GP", "GENERAL PROTECTION FAULT"),
+        14 => ("#PF", "PAGE FAULT"),
+        16 => ("#MF", "X87 FLOATING POINT"),
+        17 => ("#AC", "ALIGNMENT CHECK"),
+        18 => ("#MC", "MACHINE CHECK"),
+        19 => ("#XM", "SIMD FLOATING POINT"),
+        20 => ("#VE", "VIRTUALIZATION"),
+        30 => ("#SX", "SECURITY EXCEPTION"),
+        _ => ("#??", "RESERVED"),
+    }
+}
+
+/// The error code of #TS, #NP, #SS and #GP, which names the segment
+/// selector or IDT entry that caused the exception.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SelectorErrorCode(pub u64);
+
+impl SelectorErrorCode {
+    /// Whether the exception was caused by an event external to the program,
+    /// e.g. a hardware interrupt.
+    pub fn external(self) -> bool {
+        self.0 & 1 != 0
+    }
+
+    /// The descriptor table the index refers to.
+    pub fn table(self) -> &'static str {
+        match (self.0 >> 1) & 0b11 {
+            0b00 => "GDT",
+            0b10 => "LDT",
+            _ => "IDT",
+        }
+    }
+
+    /// The index of the descriptor in the table.
+    pub fn index(self) -> u64 {
+        (self.0 >> 3) & 0x1fff
+    }
+}
+
+impl fmt::Display for SelectorErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // 错误码为0表示异常与选择子无关
+        if self.0 == 0 {
+            return write!(f, "{:#x}", self.0);
+        }
+        write!(f, "{:#x} ({} index {}", self.0, self.table(), self.index())?;
+        if self.external() {
+            write!(f, ", external event")?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// The register state at the time of an exception: the interrupt stack frame
+/// pushed by the CPU, the general purpose registers if the exception entered
+/// through a `trap_entry!` stub, and the control registers.
+pub struct Registers<'a> {
+    stack_frame: &'a InterruptStackFrameValue,
+    general: Option<&'a TrapFrame>,
+    cr0: u64,
+    cr2: u64,
+    cr3: u64,
+    cr4: u64,
+}
+
+impl<'a> Registers<'a> {
+    /// Reads the control registers, must be called before the handler does
+    /// anything that could change them, e.g. cause a page fault.
+    pub fn capture(stack_frame: &'a InterruptStackFrameValue) -> Self {
+        let (level_4_frame, flags) = Cr3::read();
+        Registers {
+            stack_frame,
+            general: None,
+            cr0: Cr0::read_raw(),
+            cr2: Cr2::read().as_u64(),
+            cr3: level_4_frame.start_address().as_u64() | flags.bits(),
+            cr4: Cr4::read_raw(),
+        }
+    }
+
+    /// Like `capture`, but also includes the general purpose registers.
+    pub fn capture_trap_frame(frame: &'a TrapFrame) -> Self {
+        Registers { general: Some(frame), ..Registers::capture(&frame.stack_frame) }
+    }
+}
+
+impl fmt::Display for Registers<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let frame = self.stack_frame;
+        let rip = frame.instruction_pointer;
+        write!(f, "RIP: {:#018x}", rip.as_u64())?;
+        if let Some((name, offset)) = backtrace::symbolize(rip) {
+            write!(f, " ({}+{:#x})", name, offset)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "RSP: {:#018x}  RFLAGS: {:#010x}", frame.stack_pointer.as_u64(), frame.cpu_flags)?;
+        writeln!(f, "CS:  {:#06x}  SS: {:#06x}", frame.code_segment, frame.stack_segment)?;
+        if let Some(g) = self.general {
+            writeln!(f, "RAX: {:#018x}  RBX: {:#018x}  RCX: {:#018x}", g.rax, g.rbx, g.rcx)?;
+            writeln!(f, "RDX: {:#018x}  RSI: {:#018x}  RDI: {:#018x}", g.rdx, g.rsi, g.rdi)?;
+            writeln!(f, "RBP: {:#018x}  R8:  {:#018x}  R9:  {:#018x}", g.rbp, g.r8, g.r9)?;
+            writeln!(f, "R10: {:#018x}  R11: {:#018x}  R12: {:#018x}", g.r10, g.r11, g.r12)?;
+            writeln!(f, "R13: {:#018x}  R14: {:#018x}  R15: {:#018x}", g.r13, g.r14, g.r15)?;
+        }
+        writeln!(f, "CR0: {:#018x}  CR2: {:#018x}", self.cr0, self.cr2)?;
+        write!(f, "CR3: {:#018x}  CR4: {:#018x}", self.cr3, self.cr4)
+    }
+}
+
+/// The panic message of an exception that the kernel cannot handle.
+pub struct CrashReport<'a> {
+    vector: u8,
+    error_code: Option<u64>,
+    cause: Option<&'a dyn fmt::Display>,
+    registers: Registers<'a>,
+}
+
+impl<'a> CrashReport<'a> {
+    pub fn new(vector: u8, error_code: Option<u64>, stack_frame: &'a InterruptStackFrameValue) -> Self {
+        CrashReport { vector, error_code, cause: None, registers: Registers::capture(stack_frame) }
+    }
+
+    /// Creates the report of an exception that entered through a
+    /// `trap_entry!` stub, including the general purpose registers.
+    pub fn from_trap_frame(frame: &'a TrapFrame) -> Self {
+        let vector = frame.vector as u8;
+        CrashReport {
+            vector,
+            error_code: if has_error_code(vector) { Some(frame.error_code) } else { None },
+            cause: None,
+            registers: Registers::capture_trap_frame(frame),
+        }
+    }
+
+    /// Adds a line that explains why the exception could not be handled.
+    pub fn with_cause(mut self, cause: &'a dyn fmt::Display) -> Self {
+        self.cause = Some(cause);
+        self
+    }
+}
+
+impl fmt::Display for CrashReport<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (mnemonic, name) = vector_name(self.vector);
+        writeln!(f, "EXCEPTION: {} ({}, vector {})", name, mnemonic, self.vector)?;
+        if let Some(cause) = self.cause {
+            writeln!(f, "Cause: {}", cause)?;
+        }
+        match (self.vector, self.error_code) {
+            (10..=13, Some(code)) => writeln!(f, "Error Code: {}", SelectorErrorCode(code))?,
+            (14, Some(code)) => {
+                writeln!(f, "Error Code: {:#x} ({:?})", code, PageFaultErrorCode::from_bits_truncate(code))?
+            }
+            (_, Some(code)) => writeln!(f, "Error Code: {:#x}", code)?,
+            (_, None) => {}
+        }
+        write!(f, "{}", self.registers)
+    }
+}
+
+/// Returns whether the CPU pushes an error code for the exception vector.
+pub fn has_error_code(vector: u8) -> bool {
+    matches!(vector, 8 | 10..=14 | 17 | 21 | 29 | 30)
+}
+
+/// The handler of all exceptions without a dedicated handler: prints the
+/// backtrace of the interrupted code and panics with a crash report.
+extern "C" fn crash(frame: &mut TrapFrame) {
+    let report = CrashReport::from_trap_frame(frame);
+    backtrace::print_context(frame.stack_frame.instruction_pointer, frame.rbp);
+    panic!("{}", report);
+}
+
+crate::trap_entry!(divide_error_entry, 0, crash);
+crate::trap_entry!(debug_entry, 1, crash);
+crate::trap_entry!(non_maskable_interrupt_entry, 2, crash);
+crate::trap_entry!(overflow_entry, 4, crash);
+crate::trap_entry!(bound_range_exceeded_entry, 5, crash);
+crate::trap_entry!(invalid_opcode_entry, 6, crash);
+crate::trap_entry!(device_not_available_entry, 7, crash);
+crate::trap_entry!(invalid_tss_entry, 10, crash, error_code);
+crate::trap_entry!(segment_not_present_entry, 11, crash, error_code);
+crate::trap_entry!(stack_segment_fault_entry, 12, crash, error_code);
+crate::trap_entry!(general_protection_fault_entry, 13, crash, error_code);
+crate::trap_entry!(x87_floating_point_entry, 16, crash);
+crate::trap_entry!(alignment_check_entry, 17, crash, error_code);
+crate::trap_entry!(simd_floating_point_entry, 19, crash);
+crate::trap_entry!(virtualization_entry, 20, crash);
+crate::trap_entry!(security_exception_entry, 30, crash, error_code);
+
+// 机器检查异常无法恢复，不需要保存通用寄存器
+extern "x86-interrupt" fn machine_check_handler(stack_frame: &mut InterruptStackFrame) -> ! {
+    let report = CrashReport::new(18, None, stack_frame);
+    backtrace::print_interrupted(stack_frame);
+    panic!("{}", report);
+}
+
+/// Installs the handlers of all exceptions except breakpoint, double fault
+/// and page fault, which `interrupts` handles itself.
+pub(super) fn set_handlers(idt: &mut InterruptDescriptorTable) {
+    set_trap_stub(&mut idt.divide_error, divide_error_entry);
+    set_trap_stub(&mut idt.debug, debug_entry);
+    set_trap_stub(&mut idt.non_maskable_interrupt, non_maskable_interrupt_entry);
+    set_trap_stub(&mut idt.overflow, overflow_entry);
+    set_trap_stub(&mut idt.bound_range_exceeded, bound_range_exceeded_entry);
+    set_trap_stub(&mut idt.invalid_opcode, invalid_opcode_entry);
+    set_trap_stub(&mut idt.device_not_available, device_not_available_entry);
+    set_trap_stub_with_error_code(&mut idt.invalid_tss, invalid_tss_entry);
+    set_trap_stub_with_error_code(&mut idt.segment_not_present, segment_not_present_entry);
+    set_trap_stub_with_error_code(&mut idt.stack_segment_fault, stack_segment_fault_entry);
+    set_trap_stub_with_error_code(&mut idt.general_protection_fault, general_protection_fault_entry);
+    set_trap_stub(&mut idt.x87_floating_point, x87_floating_point_entry);
+    set_trap_stub_with_error_code(&mut idt.alignment_check, alignment_check_entry);
+    idt.machine_check.set_handler_fn(machine_check_handler);
+    set_trap_stub(&mut idt.simd_floating_point, simd_floating_point_entry);
+    set_trap_stub(&mut idt.virtualization, virtualization_entry);
+    set_trap_stub_with_error_code(&mut idt.security_exception, security_exception_entry);
+}
@@ -0,0 +1,288 @@
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+use x86_64::structures::idt::InterruptDescriptorTable;
+use super::apic::{self, ApicError, IO_APIC, LOCAL_APIC};
+use super::trap::{set_trap_stub, TrapFrame, TrapStub};
+use super::{PICS, PIC_1_OFFSET};
+
+/// Number of interrupt lines of the two chained 8259 PICs.
+pub const IRQ_COUNT: usize = 16;
+
+/// The line of the master PIC that the slave PIC is connected to.
+const CASCADE_IRQ: u8 = 2;
+
+const PIC_1_COMMAND: u16 = 0x20;
+const PIC_1_DATA: u16 = 0x21;
+const PIC_2_COMMAND: u16 = 0xa0;
+const PIC_2_DATA: u16 = 0xa1;
+/// OCW3 command that makes the next read of the command port return the
+/// in-service register.
+const READ_ISR: u8 = 0x0b;
+/// Non-specific end of interrupt command.
+const EOI: u8 = 0x20;
+
+/// A handler registered with `register_irq`.
+///
+/// Handlers run with interrupts disabled and must not send an EOI, the
+/// dispatcher does that after the handler returns.
+pub type IrqHandler = fn(&mut TrapFrame);
+
+/// The error returned by `register_irq` and `unregister_irq`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqError {
+    /// The line does not exist or is the cascade line of the slave PIC.
+    InvalidIrq,
+    /// Another handler is registered for the line.
+    Busy,
+    /// No handler is registered for the line.
+    NotRegistered,
+}
+
+impl fmt::Display for IrqError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IrqError::InvalidIrq => write!(f, "invalid irq line"),
+            IrqError::Busy => write!(f, "irq line already has a handler"),
+            IrqError::NotRegistered => write!(f, "irq line has no handler"),
+        }
+    }
+}
+
+// 只用于初始化下面的数组
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_HANDLER: AtomicUsize = AtomicUsize::new(0);
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicU64 = AtomicU64::new(0);
+
+// 处理函数以地址形式保存，0表示未注册，中断处理路径上不需要加锁
+static HANDLERS: [AtomicUsize; IRQ_COUNT] = [NO_HANDLER; IRQ_COUNT];
+
+static COUNTS: [AtomicU64; IRQ_COUNT] = [ZERO; IRQ_COUNT];
+
+static SPURIOUS_COUNTS: [AtomicU64; 2] = [ZERO; 2];
+
+// 为true时IRQ经由I/O APIC传递，EOI发送给本地APIC
+static APIC_ENABLED: AtomicBool = AtomicBool::new(false);
+
+fn check_irq(irq: u8) -> Result<usize, IrqError> {
+    if (irq as usize) < IRQ_COUNT && irq != CASCADE_IRQ {
+        Ok(irq as usize)
+    } else {
+        Err(IrqError::InvalidIrq)
+    }
+}
+
+/// Registers `handler` for the legacy IRQ line `irq` and unmasks the line.
+pub fn register_irq(irq: u8, handler: IrqHandler) -> Result<(), IrqError> {
+    let index = check_irq(irq)?;
+    HANDLERS[index]
+        .compare_exchange(0, handler as usize, Ordering::SeqCst, Ordering::SeqCst)
+        .map_err(|_| IrqError::Busy)?;
+    unmask_irq(irq);
+    Ok(())
+}
+
+/// Masks the line `irq` and removes its handler.
+pub fn unregister_irq(irq: u8) -> Result<(), IrqError> {
+    let index = check_irq(irq)?;
+    mask_irq(irq);
+    match HANDLERS[index].swap(0, Ordering::SeqCst) {
+        0 => Err(IrqError::NotRegistered),
+        _ => Ok(()),
+    }
+}
+
+/// Returns the number of interrupts on the line `irq`, not counting spurious ones.
+pub fn irq_count(irq: u8) -> u64 {
+    COUNTS.get(irq as usize).map_or(0, |count| count.load(Ordering::Relaxed))
+}
+
+/// Returns the number of spurious interrupts of the 8259 PIC on the line
+/// `irq`, which is always 0 for lines other than 7 and 15.
+pub fn spurious_count(irq: u8) -> u64 {
+    match irq {
+        7 => SPURIOUS_COUNTS[0].load(Ordering::Relaxed),
+        15 => SPURIOUS_COUNTS[1].load(Ordering::Relaxed),
+        _ => 0,
+    }
+}
+
+fn data_port(irq: u8) -> (Port<u8>, u8) {
+    if irq < 8 {
+        (Port::new(PIC_1_DATA), irq)
+    } else {
+        (Port::new(PIC_2_DATA), irq - 8)
+    }
+}
+
+/// Returns whether IRQs are delivered through the I/O APIC, see `enable_apic`.
+pub fn apic_enabled() -> bool {
+    APIC_ENABLED.load(Ordering::SeqCst)
+}
+
+/// Masks the line `irq`, so that the interrupt controller no longer raises
+/// its interrupts.
+pub fn mask_irq(irq: u8) {
+    if apic_enabled() {
+        set_io_apic_masked(irq, true);
+    } else {
+        update_mask(irq, |mask, bit| mask | bit);
+    }
+}
+
+/// Unmasks the line `irq`.
+pub fn unmask_irq(irq: u8) {
+    if apic_enabled() {
+        set_io_apic_masked(irq, false);
+    } else {
+        update_mask(irq, |mask, bit| mask & !bit);
+    }
+}
+
+/// Returns whether the line `irq` is masked.
+pub fn is_masked(irq: u8) -> bool {
+    if apic_enabled() {
+        return without_interrupts(|| {
+            IO_APIC.lock().as_mut().map_or(true, |io| io.is_masked(apic::io_apic_pin(irq)))
+        });
+    }
+    let (mut port, bit) = data_port(irq);
+    without_interrupts(|| {
+        let _pics = PICS.lock();
+        unsafe { port.read() & 1 << bit != 0 }
+    })
+}
+
+fn set_io_apic_masked(irq: u8, masked: bool) {
+    without_interrupts(|| {
+        if let Some(io) = IO_APIC.lock().as_mut() {
+            io.set_masked(apic::io_apic_pin(irq), masked);
+        }
+    });
+}
+
+fn update_mask<F: FnOnce(u8, u8) -> u8>(irq: u8, f: F) {
+    let (mut port, bit) = data_port(irq);
+    // 中断处理路径也会锁定PICS，持有锁时必须关中断
+    without_interrupts(|| {
+        let _pics = PICS.lock();
+        unsafe {
+            let mask = port.read();
+            port.write(f(mask, 1 << bit));
+        }
+    });
+}
+
+/// Initializes the PICs and masks all lines except the cascade line, which
+/// `register_irq` unmasks again.
+pub fn init_pics() {
+    without_interrupts(|| {
+        let mut pics = PICS.lock();
+        unsafe {
+            pics.initialize();
+            Port::<u8>::new(PIC_1_DATA).write(!(1 << CASCADE_IRQ));
+            Port::<u8>::new(PIC_2_DATA).write(0xff);
+        }
+    });
+}
+
+/// Switches from the 8259 PIC to the local APIC and the I/O APIC.
+///
+/// The IRQ lines keep their vectors and handlers, lines with a registered
+/// handler are unmasked on the I/O APIC and all lines of the PIC are masked.
+/// On error the PIC stays in use. Requires `memory::init_global`.
+pub fn enable_apic() -> Result<(), ApicError> {
+    if apic_enabled() {
+        return Ok(());
+    }
+    without_interrupts(|| {
+        apic::init()?;
+        let apic_id = LOCAL_APIC.lock().as_ref().map_or(0, |local| local.id());
+        let mut io_apic = IO_APIC.lock();
+        let io = io_apic.as_mut().expect("apic::init did not set up the i/o apic");
+        for irq in 0..IRQ_COUNT as u8 {
+            if irq == CASCADE_IRQ || apic::io_apic_pin(irq) >= io.pins() {
+                continue;
+            }
+            let masked = HANDLERS[irq as usize].load(Ordering::SeqCst) == 0;
+            io.route(apic::io_apic_pin(irq), PIC_1_OFFSET + irq, apic_id, masked);
+        }
+        let _pics = PICS.lock();
+        unsafe {
+            Port::<u8>::new(PIC_1_DATA).write(0xff);
+            Port::<u8>::new(PIC_2_DATA).write(0xff);
+        }
+        APIC_ENABLED.store(true, Ordering::SeqCst);
+        Ok(())
+    })
+}
+
+/// Returns whether the interrupt on line 7 or 15 is spurious, i.e. the PIC
+/// raised it without the line being in service.
+fn is_spurious(irq: u8) -> bool {
+    let command = match irq {
+        7 => PIC_1_COMMAND,
+        15 => PIC_2_COMMAND,
+        _ => return false,
+    };
+    let mut port = Port::<u8>::new(command);
+    unsafe {
+        port.write(READ_ISR);
+        port.read() & 0x80 == 0
+    }
+}
+
+extern "C" fn dispatch(frame: &mut TrapFrame) {
+    let irq = (frame.vector - u64::from(PIC_1_OFFSET)) as u8;
+    let apic = apic_enabled();
+    if !apic && is_spurious(irq) {
+        SPURIOUS_COUNTS[(irq / 8) as usize].fetch_add(1, Ordering::Relaxed);
+        // 从片的伪中断仍然经过了主片的级联线，主片需要EOI
+        if irq == 15 {
+            unsafe { Port::<u8>::new(PIC_1_COMMAND).write(EOI) };
+        }
+        return;
+    }
+    COUNTS[irq as usize].fetch_add(1, Ordering::Relaxed);
+    let handler = HANDLERS[irq as usize].load(Ordering::SeqCst);
+    if handler != 0 {
+        let handler: IrqHandler = unsafe { core::mem::transmute(handler) };
+        handler(frame);
+    }
+    if apic {
+        apic::end_of_interrupt();
+    } else {
+        unsafe { PICS.lock().notify_end_of_interrupt(frame.vector as u8) };
+    }
+}
+
+crate::trap_entry!(irq0_entry, PIC_1_OFFSET, dispatch);
+crate::trap_entry!(irq1_entry, PIC_1_OFFSET + 1, dispatch);
+crate::trap_entry!(irq2_entry, PIC_1_OFFSET + 2, dispatch);
+crate::trap_entry!(irq3_entry, PIC_1_OFFSET + 3, dispatch);
+crate::trap_entry!(irq4_entry, PIC_1_OFFSET + 4, dispatch);
+crate::trap_entry!(irq5_entry, PIC_1_OFFSET + 5, dispatch);
+crate::trap_entry!(irq6_entry, PIC_1_OFFSET + 6, dispatch);
+crate::trap_entry!(irq7_entry, PIC_1_OFFSET + 7, dispatch);
+crate::trap_entry!(irq8_entry, PIC_1_OFFSET + 8, dispatch);
+crate::trap_entry!(irq9_entry, PIC_1_OFFSET + 9, dispatch);
+crate::trap_entry!(irq10_entry, PIC_1_OFFSET + 10, dispatch);
+crate::trap_entry!(irq11_entry, PIC_1_OFFSET + 11, dispatch);
+crate::trap_entry!(irq12_entry, PIC_1_OFFSET + 12, dispatch);
+crate::trap_entry!(irq13_entry, PIC_1_OFFSET + 13, dispatch);
+crate::trap_entry!(irq14_entry, PIC_1_OFFSET + 14, dispatch);
+crate::trap_entry!(irq15_entry, PIC_1_OFFSET + 15, dispatch);
+
+const STUBS: [TrapStub; IRQ_COUNT] = [
+    irq0_entry, irq1_entry, irq2_entry, irq3_entry, irq4_entry, irq5_entry, irq6_entry, irq7_entry,
+    irq8_entry, irq9_entry, irq10_entry, irq11_entry, irq12_entry, irq13_entry, irq14_entry, irq15_entry,
+];
+
+/// Installs the entry stubs of all IRQ lines.
+pub(super) fn set_handlers(idt: &mut InterruptDescriptorTable) {
+    for (irq, &stub) in STUBS.iter().enumerate() {
+        set_trap_stub(&mut idt[usize::from(PIC_1_OFFSET) + irq], stub);
+    }
+}
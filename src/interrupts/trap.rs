@@ -0,0 +1,104 @@
+use x86_64::structures::idt::{Entry, EntryOptions, HandlerFunc, HandlerFuncWithErrCode, InterruptStackFrameValue};
+
+/// The complete register state of the interrupted code, saved on the stack by
+/// an entry stub created with `trap_entry!`.
+///
+/// Changes that a handler makes to the frame are restored into the registers
+/// when it returns, so a handler can e.g. skip an instruction or switch to
+/// another context.
+#[derive(Debug, Clone)]
+#[repr(C)]
+pub struct TrapFrame {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    /// The interrupt vector, pushed by the entry stub.
+    pub vector: u64,
+    /// The error code pushed by the CPU, or 0 for vectors without one.
+    pub error_code: u64,
+    /// The interrupt stack frame pushed by the CPU.
+    pub stack_frame: InterruptStackFrameValue,
+}
+
+/// An entry stub created with `trap_entry!`.
+pub type TrapStub = unsafe extern "C" fn() -> !;
+
+/// Creates a naked entry stub for an interrupt vector that saves all general
+/// purpose registers into a `TrapFrame`, calls the handler with it and
+/// restores the registers from the frame.
+///
+/// The handler must have the type `extern "C" fn(&mut TrapFrame)`. For vectors
+/// where the CPU pushes an error code, pass `error_code` as last argument and
+/// install the stub with `set_trap_stub_with_error_code`. The crate that uses
+/// this macro needs the `asm` and `naked_functions` features.
+#[macro_export]
+macro_rules! trap_entry {
+    ($name:ident, $vector:expr, $handler:path) => {
+        #[naked]
+        unsafe extern "C" fn $name() -> ! {
+            // 没有错误码的向量压入0，使所有向量的`TrapFrame`布局相同
+            asm!(
+                "push 0",
+                "push {vector}",
+                "push rax", "push rbx", "push rcx", "push rdx", "push rsi", "push rdi", "push rbp",
+                "push r8", "push r9", "push r10", "push r11", "push r12", "push r13", "push r14", "push r15",
+                "mov rdi, rsp",
+                "cld",
+                "call {handler}",
+                "pop r15", "pop r14", "pop r13", "pop r12", "pop r11", "pop r10", "pop r9", "pop r8",
+                "pop rbp", "pop rdi", "pop rsi", "pop rdx", "pop rcx", "pop rbx", "pop rax",
+                "add rsp, 16",
+                "iretq",
+                vector = const $vector,
+                handler = sym $handler,
+                options(noreturn)
+            );
+        }
+    };
+    ($name:ident, $vector:expr, $handler:path, error_code) => {
+        #[naked]
+        unsafe extern "C" fn $name() -> ! {
+            asm!(
+                "push {vector}",
+                "push rax", "push rbx", "push rcx", "push rdx", "push rsi", "push rdi", "push rbp",
+                "push r8", "push r9", "push r10", "push r11", "push r12", "push r13", "push r14", "push r15",
+                "mov rdi, rsp",
+                "cld",
+                "call {handler}",
+                "pop r15", "pop r14", "pop r13", "pop r12", "pop r11", "pop r10", "pop r9", "pop r8",
+                "pop rbp", "pop rdi", "pop rsi", "pop rdx", "pop rcx", "pop rbx", "pop rax",
+                "add rsp, 16",
+                "iretq",
+                vector = const $vector,
+                handler = sym $handler,
+                options(noreturn)
+            );
+        }
+    };
+}
+
+/// Installs an entry stub for a vector without error code.
+pub fn set_trap_stub(entry: &mut Entry<HandlerFunc>, stub: TrapStub) -> &mut EntryOptions {
+    // IDT表项只保存处理函数的地址，函数类型只用于区分有无错误码
+    entry.set_handler_fn(unsafe { core::mem::transmute::<TrapStub, HandlerFunc>(stub) })
+}
+
+/// Installs an entry stub created with the `error_code` argument.
+pub fn set_trap_stub_with_error_code(
+    entry: &mut Entry<HandlerFuncWithErrCode>,
+    stub: TrapStub,
+) -> &mut EntryOptions {
+    entry.set_handler_fn(unsafe { core::mem::transmute::<TrapStub, HandlerFuncWithErrCode>(stub) })
+}
@@ -5,6 +5,10 @@
 #![reexport_test_harness_main = "test_main"]
 #![feature(abi_x86_interrupt)]
 #![feature(alloc_error_handler)]
+#![feature(const_mut_refs)]
+#![feature(asm)]
+#![feature(global_asm)]
+#![feature(naked_functions)]
 
 pub mod serial;
 pub mod vga_buffer;
@@ -12,13 +16,18 @@ pub mod interrupts;
 pub mod gdt;
 pub mod memory;
 pub mod allocator;
+pub mod cpu;
+pub mod backtrace;
+pub mod time;
 
 extern crate alloc;
 
+use core::fmt::{self, Write};
 use core::panic::PanicInfo;
 
+use bootloader::BootInfo;
 #[cfg(test)]
-use bootloader::{entry_point, BootInfo};
+use bootloader::entry_point;
 
 #[cfg(test)]
 entry_point!(test_kernel_main);
@@ -26,7 +35,9 @@ entry_point!(test_kernel_main);
 pub fn test_runner(tests: &[&dyn Fn()]) {
     serial_println!("Running {} tests", tests.len());
     for test in tests {
+        let heap_stats = allocator::stats();
         test();
+        allocator::tracking::report_leaks(&heap_stats);
     }
     exit_qemu(QemuExitCode::Success);
 }
@@ -34,10 +45,106 @@ pub fn test_runner(tests: &[&dyn Fn()]) {
 pub fn test_panic_handler(info: &PanicInfo) -> ! {
     serial_println!("[failed]\n");
     serial_println!("Error: {}\n", info);
+    backtrace::print();
     exit_qemu(QemuExitCode::Failed);
     hlt_loop();
 }
 
+/// Collects a formatted message without using the heap, longer messages are
+/// truncated.
+pub struct PanicBuffer {
+    bytes: [u8; 1024],
+    len: usize,
+}
+
+impl PanicBuffer {
+    pub const fn new() -> Self {
+        PanicBuffer { bytes: [0; 1024], len: 0 }
+    }
+
+    pub fn as_str(&self) -> &str {
+        // 截断可能拆开一个多字节字符，只返回完整的部分
+        match core::str::from_utf8(&self.bytes[..self.len]) {
+            Ok(s) => s,
+            Err(error) => core::str::from_utf8(&self.bytes[..error.valid_up_to()]).unwrap_or(""),
+        }
+    }
+}
+
+impl Default for PanicBuffer {
+    fn default() -> Self {
+        PanicBuffer::new()
+    }
+}
+
+impl Write for PanicBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = s.len().min(self.bytes.len() - self.len);
+        self.bytes[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+/// The panic handler of tests that are expected to panic: exits QEMU with
+/// success if the panic message contains all of `expected`, otherwise fails
+/// like `test_panic_handler`.
+pub fn expect_panic_message(info: &PanicInfo, expected: &[&str]) -> ! {
+    expect_panic_message_with(info, expected, "")
+}
+
+/// Like `expect_panic_message` for the crash report of a CPU exception raised
+/// in `function`, e.g. `"divide_error::main"`.
+///
+/// The report must also contain the RIP line, followed by the name of
+/// `function` if the kernel has a symbol table (see `backtrace::has_symbols`).
+pub fn expect_crash_report(info: &PanicInfo, function: &str, expected: &[&str]) -> ! {
+    let mut rip = PanicBuffer::new();
+    if backtrace::has_symbols() {
+        let _ = write!(rip, "({}+", function);
+    } else {
+        let _ = rip.write_str("RIP: ");
+    }
+    expect_panic_message_with(info, expected, rip.as_str())
+}
+
+fn expect_panic_message_with(info: &PanicInfo, expected: &[&str], also_expected: &str) -> ! {
+    let mut message = PanicBuffer::new();
+    let _ = write!(message, "{}", info);
+    let message = message.as_str();
+    if expected.iter().all(|expected| message.contains(expected)) && message.contains(also_expected) {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+        hlt_loop();
+    }
+    test_panic_handler(info)
+}
+
+/// Sets up the memory management of integration tests like `kernel_main`
+/// does: the page table mapper, the global frame allocator and the reserved
+/// physical memory window of `memory::vm`. Call it after `init`.
+pub fn test_init_memory(boot_info: &'static BootInfo) {
+    init_memory(boot_info, false);
+}
+
+/// Like `test_init_memory`, but also initializes the kernel heap.
+pub fn test_init_memory_with_heap(boot_info: &'static BootInfo) {
+    init_memory(boot_info, true);
+}
+
+fn init_memory(boot_info: &'static BootInfo, heap: bool) {
+    let phys_mem_offset = x86_64::VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        memory::BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    memory::vm::init(&boot_info.memory_map).expect("reserving the physical memory window failed");
+    if heap {
+        allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    }
+    memory::init_global(mapper, frame_allocator);
+}
+
 /// Entry point for `cargo xtest`
 #[cfg(test)]
 fn test_kernel_main(_boot_info: &'static BootInfo) -> ! {
@@ -71,9 +178,14 @@ pub fn exit_qemu(exit_code: QemuExitCode) {
 }
 
 pub fn init(){
+    memory::enable_protection();
+    cpu::enable_security_features();
+    cpu::enable_pcid();
     gdt::init();
     interrupts::init_idt();
-    unsafe { interrupts::PICS.lock().initialize() };
+    interrupts::init_irqs();
+    time::set_frequency(time::DEFAULT_FREQUENCY);
+    time::rtc::init().expect("registering the rtc handler failed");
     x86_64::instructions::interrupts::enable();
 }
 
@@ -83,10 +195,19 @@ pub fn hlt_loop() -> ! {
     }
 }
 
-use linked_list_allocator::LockedHeap;
+/// The idle loop of the kernel: halts until the next interrupt and runs the
+/// expired timers, see `time::timer`.
+pub fn idle_loop() -> ! {
+    loop {
+        x86_64::instructions::hlt();
+        time::timer::run_expired();
+    }
+}
+
+use allocator::{tracking::Tracked, HeapAllocator, Locked};
 
 #[global_allocator]
-static ALLOCATOR: LockedHeap = LockedHeap::empty();
+static ALLOCATOR: Tracked<Locked<HeapAllocator>> = Tracked::new(Locked::new(HeapAllocator::new()));
 
 #[alloc_error_handler]
 fn alloc_error_handler(layout: alloc::alloc::Layout) -> ! {
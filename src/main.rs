@@ -17,7 +17,8 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
 
     use os_626::allocator;
     use os_626::memory;
-    use x86_64::{structures::paging::Page, VirtAddr};
+    use os_626::memory::vm;
+    use x86_64::{PhysAddr, VirtAddr};
 
     println!("Hello World{}", "!");
 
@@ -28,19 +29,33 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     let mut mapper = unsafe { memory::init(phys_mem_offset)};
 
     let mut frame_allocator = unsafe {
-        memory::BootInfoFrameAllocator::init(&boot_info.memory_map)
+        memory::BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
     };
 
-    let page = Page::containing_address(VirtAddr::new(0));
-
-    memory::create_example_mapping(page,&mut mapper, &mut frame_allocator);
-
-    let page_ptr: *mut u64 = page.start_address().as_mut_ptr();
-
-    unsafe { page_ptr.offset(400).write_volatile(0x_f021_f077_f065_f04e)}; // 0x_f021_f077_f065_f04e代表字符串 New！
+    vm::init(&boot_info.memory_map).expect("reserving the physical memory window failed");
 
     allocator::init_heap(&mut mapper, &mut frame_allocator)
         .expect("heap initialization failed");
+    memory::init_global(mapper, frame_allocator);
+    os_626::gdt::init_stacks().expect("allocating the interrupt stacks failed");
+    memory::stack::register_current_stack("kernel")
+        .expect("registering the kernel stack guard failed");
+    memory::protect_kernel(&boot_info.memory_map);
+    match os_626::interrupts::enable_apic() {
+        Ok(()) => println!("interrupts: using the local apic and i/o apic"),
+        Err(error) => println!("interrupts: using the 8259 pic ({})", error),
+    }
+    println!("time: booted at {}", os_626::time::rtc::now());
+    let calibration = os_626::time::calibrate();
+    println!("time: tsc at {} khz", calibration.tsc_hz / 1000);
+    if let Some(hz) = calibration.apic_timer_hz {
+        println!("time: local apic timer at {} khz", hz / 1000);
+    }
+
+    // 将VGA缓冲区映射为不可缓存的设备内存
+    let mut vga = memory::map_mmio(PhysAddr::new(0xb8000), 4096)
+        .expect("mapping the vga buffer failed");
+    vga.write::<u64>(400 * 8, 0x_f021_f077_f065_f04e); // 0x_f021_f077_f065_f04e代表字符串 New！
 
     let heap_value = Box::new(41);
     println!("heap_value at {:p}", heap_value);
@@ -61,7 +76,7 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     #[cfg(test)]
     test_main();
     println!("It did not crash!");
-    os_626::hlt_loop();
+    os_626::idle_loop();
 }
 
 /// 这个函数将在panic时被调用
@@ -71,6 +86,7 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     println!("{}", info);
+    os_626::backtrace::print();
     os_626::hlt_loop();
 }
 
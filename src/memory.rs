@@ -1,11 +1,32 @@
 use x86_64::{
     structures::paging::{
         PageTable, OffsetPageTable, Page, PhysFrame, Mapper, Size4KiB, FrameAllocator,
+        PageSize, PageTableFlags, Size1GiB, Size2MiB,
     },
     VirtAddr,
     PhysAddr
 };
-use bootloader::bootinfo::{ MemoryMap, MemoryRegionType};
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+
+mod bitmap;
+pub mod address_space;
+pub mod buddy;
+pub mod cow;
+mod dump;
+mod mmio;
+mod protect;
+pub mod fault;
+pub mod stack;
+pub mod user;
+pub mod vm;
+
+pub use self::address_space::AddressSpace;
+pub use self::bitmap::BitmapFrameAllocator;
+pub use self::buddy::BuddyFrameAllocator;
+pub use self::protect::{enable_protection, protect_kernel};
+pub use self::mmio::{map_mmio, map_mmio_with, CacheMode, Mmio};
+pub use self::dump::{mapped_ranges, print_mappings, MappedRange, MappedRanges};
 
 pub struct EmptyFrameAllocator;
 
@@ -15,55 +36,52 @@ unsafe impl FrameAllocator<Size4KiB> for EmptyFrameAllocator {
     }
 }
 
-/// A FrameAllocator that returns usable frames from the bootloader's memory map.
-pub struct BootInfoFrameAllocator {
-    memory_map: &'static MemoryMap,
-    next: usize,
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+// 内核地址空间的4级页表，所有`AddressSpace`共享其中的内核部分
+static KERNEL_LEVEL_4_FRAME: AtomicU64 = AtomicU64::new(0);
+
+pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    PHYSICAL_MEMORY_OFFSET.store(physical_memory_offset.as_u64(), Ordering::SeqCst);
+    let (level_4_frame, _) = x86_64::registers::control::Cr3::read();
+    KERNEL_LEVEL_4_FRAME.store(level_4_frame.start_address().as_u64(), Ordering::SeqCst);
+    let level_4_table = active_level_4_table(physical_memory_offset);
+    OffsetPageTable::new(level_4_table, physical_memory_offset)
 }
 
-impl BootInfoFrameAllocator {
-    /// Create a FrameAllocator from the passed memory map.
-    ///
-    /// This function is unsafe because the caller must guarantee that the passed
-    /// memory map is valid. The main requirement is that all frames that are marked
-    /// as `USABLE` in it are really unused.
-    pub unsafe fn init(memory_map: &'static MemoryMap) -> Self {
-        BootInfoFrameAllocator {
-            memory_map,
-            next: 0,
-        }
-    }
+/// Returns the virtual address at which the complete physical memory is
+/// mapped, as passed to `init`.
+pub fn physical_memory_offset() -> VirtAddr {
+    VirtAddr::new(PHYSICAL_MEMORY_OFFSET.load(Ordering::SeqCst))
 }
 
-impl BootInfoFrameAllocator {
-    /// Returns an iterator over the usable frames specified in the memory map.
-    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
-        // get usable regions from memory map
-        let regions = self.memory_map.iter();
-        let usable_regions = regions
-            .filter(|r| r.region_type == MemoryRegionType::Usable);
-        // map each region to its address range
-        let addr_ranges = usable_regions
-            .map(|r| r.range.start_addr()..r.range.end_addr());
-        // transform to an iterator of frame start addresses
-        let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
-        // create `PhysFrame` types from the start addresses
-        frame_addresses
-            .map(|addr|PhysFrame::containing_address(PhysAddr::new(addr)))
-    }
+/// Returns the virtual address through which the given physical address can
+/// be accessed in the physical memory mapping. Requires a prior call to `init`.
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    physical_memory_offset() + addr.as_u64()
 }
 
-unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
-    fn allocate_frame(&mut self) -> Option<PhysFrame> {
-        let frame = self.usable_frames().nth(self.next);
-        self.next += 1;
-        frame
-    }
+/// Returns the frame of the kernel's level 4 table, which was active during `init`.
+fn kernel_level_4_frame() -> PhysFrame {
+    PhysFrame::containing_address(PhysAddr::new(KERNEL_LEVEL_4_FRAME.load(Ordering::SeqCst)))
 }
 
-pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
-    let level_4_table = active_level_4_table(physical_memory_offset);
-    OffsetPageTable::new(level_4_table, physical_memory_offset)
+/// The kernel page table, available after `init_global`.
+///
+/// When both locks are needed, `MAPPER` must be locked before
+/// `FRAME_ALLOCATOR`. Neither lock may be held while allocating heap memory,
+/// because growing the heap takes both of them while the heap allocator is
+/// locked, see `allocator::grow`.
+pub static MAPPER: Mutex<Option<OffsetPageTable<'static>>> = Mutex::new(None);
+
+/// The kernel frame allocator, available after `init_global`.
+pub static FRAME_ALLOCATOR: Mutex<Option<BitmapFrameAllocator>> = Mutex::new(None);
+
+/// Hands the kernel page table and frame allocator over to the global
+/// `MAPPER` and `FRAME_ALLOCATOR`, so that code without access to the values
+/// created at boot (e.g. the heap allocator) can map memory.
+pub fn init_global(mapper: OffsetPageTable<'static>, frame_allocator: BitmapFrameAllocator) {
+    *MAPPER.lock() = Some(mapper);
+    *FRAME_ALLOCATOR.lock() = Some(frame_allocator);
 }
 
 // 返回4级页表的可变引用
@@ -89,15 +107,62 @@ unsafe fn active_level_4_table(physical_memory_offset: VirtAddr)->&'static mut P
 }
 
 
+/// The size of the page that maps a translated address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MappedPageSize {
+    Size4KiB,
+    Size2MiB,
+    Size1GiB,
+}
+
+impl MappedPageSize {
+    /// Returns the size of the page in bytes.
+    pub fn size(self) -> u64 {
+        match self {
+            MappedPageSize::Size4KiB => Size4KiB::SIZE,
+            MappedPageSize::Size2MiB => Size2MiB::SIZE,
+            MappedPageSize::Size1GiB => Size1GiB::SIZE,
+        }
+    }
+}
+
+/// The result of translating a virtual address through the active page table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Translation {
+    /// The physical address the virtual address is mapped to.
+    pub addr: PhysAddr,
+    /// The size of the page containing the address.
+    pub page_size: MappedPageSize,
+    /// The flags of the page table entry that maps the page.
+    pub flags: PageTableFlags,
+}
+
+/// Translates the given virtual address to the mapped physical address, or
+/// `None` if the address is not mapped.
+///
+/// # Safety
+///
+/// The caller must guarantee that the complete physical memory is mapped to
+/// virtual memory at the passed `physical_memory_offset`.
 pub unsafe fn translate_addr(addr: VirtAddr, physical_memory_offset: VirtAddr)
     -> Option<PhysAddr> {
-    translate_addr_inner(addr,physical_memory_offset)
+    translate_addr_inner(addr, physical_memory_offset).map(|t| t.addr)
+}
+
+/// Like `translate_addr`, but also returns the size and flags of the page
+/// that maps the address. 1GiB and 2MiB pages are supported.
+///
+/// # Safety
+///
+/// Same as for `translate_addr`.
+pub unsafe fn translate(addr: VirtAddr, physical_memory_offset: VirtAddr)
+    -> Option<Translation> {
+    translate_addr_inner(addr, physical_memory_offset)
 }
 
 fn translate_addr_inner(addr: VirtAddr, physical_memory_offset: VirtAddr)
-                        -> Option<PhysAddr>
+                        -> Option<Translation>
 {
-    use x86_64::structures::paging::page_table::FrameError;
     use x86_64::registers::control::Cr3;
 
     // read the active level 4 frame from the CR3 register
@@ -109,7 +174,7 @@ fn translate_addr_inner(addr: VirtAddr, physical_memory_offset: VirtAddr)
     let mut frame = level_4_table_frame;
 
     // traverse the multi-level page table
-    for &index in &table_indexes {
+    for (level, &index) in table_indexes.iter().enumerate() {
         // convert the frame into a page table reference
         let virt = physical_memory_offset + frame.start_address().as_u64();
         let table_ptr: *const PageTable = virt.as_ptr();
@@ -117,15 +182,33 @@ fn translate_addr_inner(addr: VirtAddr, physical_memory_offset: VirtAddr)
 
         // read the page table entry and update `frame`
         let entry = &table[index];
-        frame = match entry.frame() {
-            Ok(frame) => frame,
-            Err(FrameError::FrameNotPresent) => return None,
-            Err(FrameError::HugeFrame) => panic!("huge pages not supported"),
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT) {
+            return None;
+        }
+
+        // 3级页表中的大页映射1GiB，2级页表中的大页映射2MiB，1级页表项总是4KiB
+        let page_size = match level {
+            1 if flags.contains(PageTableFlags::HUGE_PAGE) => MappedPageSize::Size1GiB,
+            2 if flags.contains(PageTableFlags::HUGE_PAGE) => MappedPageSize::Size2MiB,
+            3 => MappedPageSize::Size4KiB,
+            _ => {
+                frame = PhysFrame::containing_address(entry.addr());
+                continue;
+            }
         };
+
+        // calculate the physical address by adding the offset inside the page
+        // 大页表项的第12位是PAT位，不属于帧地址，需要按页大小对齐
+        let offset = addr.as_u64() & (page_size.size() - 1);
+        return Some(Translation {
+            addr: entry.addr().align_down(page_size.size()) + offset,
+            page_size,
+            flags,
+        });
     }
 
-    // calculate the physical address by adding the page offset
-    Some(frame.start_address() + u64::from(addr.page_offset()))
+    unreachable!("the level 1 entry always terminates the walk")
 }
 
 // 创建从虚拟内存页到物理内存帧`0xb8000`的映射
@@ -133,14 +216,15 @@ pub fn create_example_mapping(
     page: Page,
     mapper: &mut OffsetPageTable,
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
-) {
+) -> Result<(), vm::VmError> {
     use x86_64::structures::paging::PageTableFlags as Flags;
 
     let frame = PhysFrame::containing_address(PhysAddr::new(0xb8000));
-    let flags = Flags::PRESENT | Flags::WRITABLE;
+    let flags = Flags::PRESENT | Flags::WRITABLE | Flags::NO_EXECUTE;
 
     let map_to_result = unsafe {
         mapper.map_to(page, frame, flags, frame_allocator)
     };
-    map_to_result.expect("map_to failed").flush();
+    map_to_result?.flush();
+    Ok(())
 }
@@ -0,0 +1,351 @@
+use core::sync::atomic::{AtomicU16, Ordering};
+use x86_64::{
+    registers::control::{Cr3, Cr4, Cr4Flags},
+    structures::paging::{
+        page_table::{PageTableEntry, PageTableIndex}, FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable,
+        Page, PageTable, PageTableFlags, PhysFrame,
+    },
+    PhysAddr, VirtAddr,
+};
+use super::cow::{self, COPY_ON_WRITE};
+use super::user::{USER_END, USER_START};
+use super::vm::VmError;
+use super::{kernel_level_4_frame, phys_to_virt, physical_memory_offset, FRAME_ALLOCATOR};
+
+/// The level 4 entries that belong to the user part of the address space, all
+/// other entries are shared with the kernel.
+const USER_ENTRIES: core::ops::Range<usize> =
+    (USER_START >> 39) as usize..(USER_END >> 39) as usize;
+
+/// The largest process-context identifier, PCID 0 is used by the kernel.
+const MAX_PCID: u16 = 4095;
+
+static NEXT_PCID: AtomicU16 = AtomicU16::new(1);
+
+/// An address space with its own user part and the kernel part shared with
+/// all other address spaces.
+///
+/// The page tables and all frames mapped in the user part belong to the
+/// address space and are freed when it is dropped. Frames that are shared
+/// with a copy created by `clone_cow` are freed by the last owner.
+pub struct AddressSpace {
+    level_4_frame: PhysFrame,
+    pcid: u16,
+}
+
+impl AddressSpace {
+    /// Creates an address space with an empty user part.
+    ///
+    /// Requires `memory::init_global`.
+    pub fn new() -> Result<Self, VmError> {
+        let frame: PhysFrame = FRAME_ALLOCATOR
+            .lock()
+            .as_mut()
+            .ok_or(VmError::NotInitialized)?
+            .allocate_frame()
+            .ok_or(VmError::OutOfMemory)?;
+        let space = AddressSpace {
+            level_4_frame: frame,
+            pcid: allocate_pcid(),
+        };
+        let table = unsafe { space.level_4_table() };
+        table.zero();
+        space.sync_kernel_entries();
+        Ok(space)
+    }
+
+    /// Returns the frame of the level 4 table, i.e. the value loaded into CR3.
+    pub fn level_4_frame(&self) -> PhysFrame {
+        self.level_4_frame
+    }
+
+    /// Returns the process-context identifier used when PCIDs are enabled.
+    pub fn pcid(&self) -> u16 {
+        self.pcid
+    }
+
+    /// Maps the user page to a newly allocated, zeroed frame and returns the frame.
+    ///
+    /// `USER_ACCESSIBLE` is added to the flags.
+    pub fn map_user_page(&mut self, page: Page, flags: PageTableFlags) -> Result<PhysFrame, VmError> {
+        let frame: PhysFrame = FRAME_ALLOCATOR
+            .lock()
+            .as_mut()
+            .ok_or(VmError::NotInitialized)?
+            .allocate_frame()
+            .ok_or(VmError::OutOfMemory)?;
+        unsafe {
+            let ptr: *mut u8 = phys_to_virt(frame.start_address()).as_mut_ptr();
+            core::ptr::write_bytes(ptr, 0, 4096);
+        }
+        match unsafe { self.map_to(page, frame, flags) } {
+            Ok(()) => Ok(frame),
+            Err(error) => {
+                free_frame(frame);
+                Err(error)
+            }
+        }
+    }
+
+    /// Maps the user page to the given frame. The address space takes
+    /// ownership of the frame and frees it when the page is unmapped.
+    ///
+    /// `USER_ACCESSIBLE` is added to the flags.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that the frame is not used otherwise.
+    pub unsafe fn map_to(&mut self, page: Page, frame: PhysFrame, flags: PageTableFlags) -> Result<(), VmError> {
+        if !is_user_page(page) {
+            return Err(VmError::InvalidRange);
+        }
+        let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+        let frame_allocator = frame_allocator.as_mut().ok_or(VmError::NotInitialized)?;
+        let flush = self.mapper().map_to(page, frame, flags, frame_allocator)?;
+        if self.is_active() {
+            flush.flush();
+        } else {
+            // 其他地址空间的TLB项在切换时刷新
+            flush.ignore();
+        }
+        Ok(())
+    }
+
+    /// Creates a copy of this address space that shares all user frames.
+    ///
+    /// Writable pages are made read-only and marked `COPY_ON_WRITE` in both
+    /// address spaces. The first write to such a page copies the frame, see
+    /// `cow::resolve_write_fault`.
+    pub fn clone_cow(&mut self) -> Result<AddressSpace, VmError> {
+        let mut child = AddressSpace::new()?;
+        let table = unsafe { self.level_4_table() };
+        let result = for_each_user_entry(table, &mut |page, entry| {
+            let frame = PhysFrame::containing_address(entry.addr());
+            let mut flags = entry.flags() - (PageTableFlags::ACCESSED | PageTableFlags::DIRTY);
+            if flags.contains(PageTableFlags::WRITABLE) {
+                flags = (flags - PageTableFlags::WRITABLE) | COPY_ON_WRITE;
+            }
+            if !cow::share(frame) {
+                return Err(VmError::TableFull);
+            }
+            entry.set_flags(flags);
+            if let Err(error) = unsafe { child.map_to(page, frame, flags) } {
+                cow::unshare(frame);
+                return Err(error);
+            }
+            Ok(())
+        });
+        // 父地址空间中的页变为只读，需要刷新TLB
+        if self.is_active() {
+            x86_64::instructions::tlb::flush_all();
+        }
+        result.map(|()| child)
+    }
+
+    /// Unmaps the user page and frees its frame.
+    pub fn unmap_user_page(&mut self, page: Page) -> Result<(), VmError> {
+        if !is_user_page(page) {
+            return Err(VmError::InvalidRange);
+        }
+        let (frame, flush) = unsafe { self.mapper() }
+            .unmap(page)
+            .map_err(|_| VmError::NotFound)?;
+        if self.is_active() {
+            flush.flush();
+        } else {
+            flush.ignore();
+        }
+        release_frame(frame);
+        Ok(())
+    }
+
+    /// Translates an address of the user part and returns the physical
+    /// address and the flags of the page.
+    pub fn translate(&self, addr: VirtAddr) -> Option<(PhysAddr, PageTableFlags)> {
+        let table = unsafe { self.level_4_table() };
+        let entry = user_entry(table, Page::containing_address(addr))?;
+        let offset = addr.as_u64() % 4096;
+        Some((entry.addr() + offset, entry.flags()))
+    }
+
+    /// Returns whether this address space is loaded in CR3.
+    pub fn is_active(&self) -> bool {
+        Cr3::read().0 == self.level_4_frame
+    }
+
+    /// Switches to this address space.
+    ///
+    /// The kernel entries are copied from the kernel address space first, so
+    /// that kernel mappings created after `new` are visible.
+    pub fn activate(&self) {
+        self.sync_kernel_entries();
+        unsafe { load_cr3(self.level_4_frame, self.pcid) };
+    }
+
+    /// Returns a mapper for the page tables of this address space.
+    ///
+    /// # Safety
+    ///
+    /// The caller must not create a second mapper for the same address space
+    /// at the same time.
+    unsafe fn mapper(&self) -> OffsetPageTable<'static> {
+        OffsetPageTable::new(self.level_4_table(), physical_memory_offset())
+    }
+
+    unsafe fn level_4_table(&self) -> &'static mut PageTable {
+        &mut *phys_to_virt(self.level_4_frame.start_address()).as_mut_ptr()
+    }
+
+    fn sync_kernel_entries(&self) {
+        let kernel: &PageTable = unsafe { &*phys_to_virt(kernel_level_4_frame().start_address()).as_ptr() };
+        let table = unsafe { self.level_4_table() };
+        for index in (0..512).filter(|index| !USER_ENTRIES.contains(index)) {
+            table[index] = kernel[index].clone();
+        }
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        if self.is_active() {
+            switch_to_kernel();
+        }
+        let table = unsafe { self.level_4_table() };
+        for index in USER_ENTRIES {
+            if table[index].flags().contains(PageTableFlags::PRESENT) {
+                unsafe { free_table(table[index].addr(), 3) };
+            }
+            table[index].set_unused();
+        }
+        free_frame(self.level_4_frame);
+    }
+}
+
+/// Returns the present level 1 entry that maps the given user page.
+pub(super) fn user_entry(level_4_table: &mut PageTable, page: Page) -> Option<&mut PageTableEntry> {
+    if !is_user_page(page) {
+        return None;
+    }
+    let mut table = level_4_table;
+    let indexes = [page.p4_index(), page.p3_index(), page.p2_index()];
+    for &index in indexes.iter() {
+        let flags = table[index].flags();
+        if !flags.contains(PageTableFlags::PRESENT) || flags.contains(PageTableFlags::HUGE_PAGE) {
+            return None;
+        }
+        table = unsafe { &mut *phys_to_virt(table[index].addr()).as_mut_ptr() };
+    }
+    let entry = &mut table[page.p1_index()];
+    if entry.flags().contains(PageTableFlags::PRESENT) {
+        Some(entry)
+    } else {
+        None
+    }
+}
+
+/// Switches back to the kernel address space.
+pub fn switch_to_kernel() {
+    unsafe { load_cr3(kernel_level_4_frame(), 0) };
+}
+
+/// Calls `f` for every present level 1 entry of the user part.
+fn for_each_user_entry<F>(level_4_table: &mut PageTable, f: &mut F) -> Result<(), VmError>
+where
+    F: FnMut(Page, &mut PageTableEntry) -> Result<(), VmError>,
+{
+    let table_at = |entry: &PageTableEntry| -> Option<&'static mut PageTable> {
+        let flags = entry.flags();
+        if flags.contains(PageTableFlags::PRESENT) && !flags.contains(PageTableFlags::HUGE_PAGE) {
+            Some(unsafe { &mut *phys_to_virt(entry.addr()).as_mut_ptr() })
+        } else {
+            None
+        }
+    };
+    for p4 in USER_ENTRIES {
+        let level_3_table = match table_at(&level_4_table[p4]) {
+            Some(table) => table,
+            None => continue,
+        };
+        for (p3, entry) in level_3_table.iter().enumerate() {
+            let level_2_table = match table_at(entry) {
+                Some(table) => table,
+                None => continue,
+            };
+            for (p2, entry) in level_2_table.iter().enumerate() {
+                let level_1_table = match table_at(entry) {
+                    Some(table) => table,
+                    None => continue,
+                };
+                for (p1, entry) in level_1_table.iter_mut().enumerate() {
+                    if entry.flags().contains(PageTableFlags::PRESENT) {
+                        let page = Page::from_page_table_indices(
+                            PageTableIndex::new(p4 as u16),
+                            PageTableIndex::new(p3 as u16),
+                            PageTableIndex::new(p2 as u16),
+                            PageTableIndex::new(p1 as u16),
+                        );
+                        f(page, entry)?;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Frees the page table at `addr` of the given level, all tables below it and
+/// the frames mapped by it.
+unsafe fn free_table(addr: PhysAddr, level: u8) {
+    let table: &mut PageTable = &mut *phys_to_virt(addr).as_mut_ptr();
+    for entry in table.iter_mut() {
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+        // 用户部分只包含4KiB页，大页不属于地址空间，只释放页表
+        if level == 1 {
+            release_frame(PhysFrame::containing_address(entry.addr()));
+        } else if !flags.contains(PageTableFlags::HUGE_PAGE) {
+            free_table(entry.addr(), level - 1);
+        }
+        entry.set_unused();
+    }
+    free_frame(PhysFrame::containing_address(addr));
+}
+
+/// Frees a frame that was mapped in the user part, unless another address
+/// space still maps it.
+fn release_frame(frame: PhysFrame) {
+    if cow::unshare(frame) {
+        free_frame(frame);
+    }
+}
+
+fn free_frame(frame: PhysFrame) {
+    if let Some(frame_allocator) = FRAME_ALLOCATOR.lock().as_mut() {
+        unsafe { frame_allocator.deallocate_frame(frame) };
+    }
+}
+
+fn is_user_page(page: Page) -> bool {
+    let addr = page.start_address().as_u64();
+    (USER_START..USER_END).contains(&addr)
+}
+
+fn allocate_pcid() -> u16 {
+    // PCID只是TLB的标签，每次加载CR3都刷新该PCID的TLB项，所以重复使用是安全的
+    let pcid = NEXT_PCID.fetch_add(1, Ordering::Relaxed);
+    pcid % MAX_PCID + 1
+}
+
+/// Loads the level 4 table into CR3, tagged with `pcid` if PCIDs are enabled.
+///
+/// Bit 63 is never set, so the TLB entries of `pcid` are always flushed.
+unsafe fn load_cr3(frame: PhysFrame, pcid: u16) {
+    let mut value = frame.start_address().as_u64();
+    if Cr4::read().contains(Cr4Flags::PCID) {
+        value |= u64::from(pcid);
+    }
+    asm!("mov cr3, {}", in(reg) value, options(nostack));
+}
@@ -0,0 +1,169 @@
+use core::slice;
+use x86_64::{
+    structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB},
+    PhysAddr, VirtAddr,
+};
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+
+const FRAME_SIZE: u64 = 4096;
+const BITS_PER_WORD: usize = 64;
+
+/// A physical frame allocator backed by a bitmap with one bit per 4KiB frame.
+///
+/// A set bit marks a frame as used. Frames that the memory map does not report
+/// as `Usable` stay marked forever, so they are never handed out. The bitmap
+/// itself lives in the first usable region that is large enough to hold it and
+/// is accessed through the physical memory offset mapping.
+pub struct BitmapFrameAllocator {
+    memory_map: &'static MemoryMap,
+    bitmap: &'static mut [u64],
+    bitmap_start: u64,
+    bitmap_end: u64,
+    usable_frames: usize,
+    free_frames: usize,
+    next_word: usize,
+}
+
+impl BitmapFrameAllocator {
+    /// Create a FrameAllocator from the passed memory map.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that the passed memory map is valid and that
+    /// the complete physical memory is mapped to virtual memory at the passed
+    /// `physical_memory_offset`. The main requirement is that all frames that
+    /// are marked as `USABLE` in the memory map are really unused.
+    pub unsafe fn init(memory_map: &'static MemoryMap, physical_memory_offset: VirtAddr) -> Self {
+        // 位图需要覆盖从0到最高可用地址的所有帧
+        let max_addr = usable_regions(memory_map)
+            .map(|r| r.1)
+            .max()
+            .unwrap_or(0);
+        let frame_count = (max_addr / FRAME_SIZE) as usize;
+        let words = (frame_count + BITS_PER_WORD - 1) / BITS_PER_WORD;
+        let bitmap_bytes = (words * 8) as u64;
+        let bitmap_bytes_aligned = (bitmap_bytes + FRAME_SIZE - 1) / FRAME_SIZE * FRAME_SIZE;
+
+        // 在第一个足够大的可用区域中存放位图
+        let bitmap_start = usable_regions(memory_map)
+            .find(|&(start, end)| end - start >= bitmap_bytes_aligned)
+            .map(|(start, _)| start)
+            .expect("no usable region is large enough for the frame bitmap");
+        let bitmap_end = bitmap_start + bitmap_bytes_aligned;
+
+        let bitmap_ptr: *mut u64 = (physical_memory_offset + bitmap_start).as_mut_ptr();
+        let bitmap = slice::from_raw_parts_mut(bitmap_ptr, words);
+        for word in bitmap.iter_mut() {
+            *word = u64::max_value();
+        }
+
+        let mut allocator = BitmapFrameAllocator {
+            memory_map,
+            bitmap,
+            bitmap_start,
+            bitmap_end,
+            usable_frames: 0,
+            free_frames: 0,
+            next_word: 0,
+        };
+
+        for (start, end) in usable_regions(memory_map) {
+            for addr in (start..end).step_by(FRAME_SIZE as usize) {
+                allocator.usable_frames += 1;
+                if allocator.is_allocatable(PhysAddr::new(addr)) {
+                    allocator.set_free(frame_index(PhysAddr::new(addr)));
+                }
+            }
+        }
+
+        allocator
+    }
+
+    /// Returns the number of frames that can currently be allocated.
+    pub fn free_frames(&self) -> usize {
+        self.free_frames
+    }
+
+    /// Returns the number of usable frames that are allocated, including the
+    /// frames occupied by the bitmap itself.
+    pub fn used_frames(&self) -> usize {
+        self.usable_frames - self.free_frames
+    }
+
+    /// Returns the total number of frames reported as usable by the memory map.
+    pub fn usable_frames(&self) -> usize {
+        self.usable_frames
+    }
+
+    fn set_free(&mut self, index: usize) {
+        self.bitmap[index / BITS_PER_WORD] &= !(1 << (index % BITS_PER_WORD));
+        self.free_frames += 1;
+    }
+
+    fn is_used(&self, index: usize) -> bool {
+        self.bitmap[index / BITS_PER_WORD] & (1 << (index % BITS_PER_WORD)) != 0
+    }
+
+    /// Returns whether the frame at `addr` lies in a usable region and outside
+    /// the bitmap, i.e. whether it is managed by the allocator at all.
+    fn is_allocatable(&self, addr: PhysAddr) -> bool {
+        let addr = addr.as_u64();
+        let usable = usable_regions(self.memory_map).any(|(start, end)| start <= addr && addr < end);
+        usable && !(self.bitmap_start <= addr && addr < self.bitmap_end)
+    }
+}
+
+/// Returns the `(start, end)` physical address ranges of the usable regions.
+fn usable_regions(memory_map: &'static MemoryMap) -> impl Iterator<Item = (u64, u64)> {
+    memory_map
+        .iter()
+        .filter(|r| r.region_type == MemoryRegionType::Usable)
+        .map(|r| (r.range.start_addr(), r.range.end_addr()))
+}
+
+fn frame_index(addr: PhysAddr) -> usize {
+    (addr.as_u64() / FRAME_SIZE) as usize
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        if self.free_frames == 0 {
+            return None;
+        }
+
+        // 从上次分配的位置开始查找，整字跳过已满的64个帧
+        let words = self.bitmap.len();
+        for offset in 0..words {
+            let word_index = (self.next_word + offset) % words;
+            let word = self.bitmap[word_index];
+            if word != u64::max_value() {
+                let bit = (!word).trailing_zeros() as usize;
+                self.bitmap[word_index] |= 1 << bit;
+                self.free_frames -= 1;
+                self.next_word = word_index;
+
+                let index = word_index * BITS_PER_WORD + bit;
+                let addr = PhysAddr::new(index as u64 * FRAME_SIZE);
+                return Some(PhysFrame::containing_address(addr));
+            }
+        }
+        None
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BitmapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        let index = frame_index(frame.start_address());
+        // 不可用的帧和位图所在的帧也被标记为已用，但它们不属于分配器
+        assert!(
+            self.is_allocatable(frame.start_address()),
+            "deallocating frame {:?} that is not managed by the allocator",
+            frame
+        );
+        assert!(self.is_used(index), "deallocating frame {:?} that is not allocated", frame);
+        self.set_free(index);
+        if index / BITS_PER_WORD < self.next_word {
+            self.next_word = index / BITS_PER_WORD;
+        }
+    }
+}
@@ -0,0 +1,347 @@
+use core::slice;
+use x86_64::{
+    structures::paging::{
+        FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size2MiB, Size4KiB,
+    },
+    PhysAddr, VirtAddr,
+};
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+
+const FRAME_SIZE: u64 = 4096;
+const BITS_PER_WORD: usize = 64;
+
+/// The largest block order, a block of order `n` spans `2^n` frames (4MiB here).
+pub const MAX_ORDER: usize = 10;
+
+/// Number of different block orders managed by the allocator.
+pub const ORDERS: usize = MAX_ORDER + 1;
+
+/// Order of a block of 512 frames, which is exactly one 2MiB frame.
+const HUGE_FRAME_ORDER: usize = 9;
+
+/// Header written to the start of every free block.
+///
+/// The free lists are doubly linked so that a buddy can be unlinked in
+/// constant time when it is merged.
+struct FreeBlock {
+    prev: Option<PhysAddr>,
+    next: Option<PhysAddr>,
+}
+
+/// A buddy allocator for physically contiguous, power-of-two sized blocks of frames.
+///
+/// Every block of order `n` starts at a frame number that is a multiple of `2^n`,
+/// so blocks of order 9 are valid 2MiB frames. Free blocks are kept in one
+/// intrusive free list per order, and a bitmap with one bit per possible block of
+/// every order records which blocks are currently on a free list.
+pub struct BuddyFrameAllocator {
+    physical_memory_offset: VirtAddr,
+    free_lists: [Option<PhysAddr>; ORDERS],
+    free_blocks: [usize; ORDERS],
+    bitmap: &'static mut [u64],
+    bitmap_offsets: [usize; ORDERS],
+    frame_count: usize,
+    usable_frames: usize,
+}
+
+/// A snapshot of the free memory managed by a `BuddyFrameAllocator`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuddyStats {
+    /// Number of free blocks for every order.
+    pub free_blocks: [usize; ORDERS],
+    /// Total number of free frames.
+    pub free_frames: usize,
+    /// Number of frames reported as usable by the memory map.
+    pub usable_frames: usize,
+}
+
+impl BuddyStats {
+    /// Returns the order of the largest free block, if any.
+    pub fn largest_free_order(&self) -> Option<usize> {
+        (0..ORDERS).rev().find(|&order| self.free_blocks[order] > 0)
+    }
+
+    /// Returns the percentage of free frames that cannot be used for an
+    /// allocation of the given order because they sit in smaller blocks.
+    ///
+    /// `0` means all free memory is available in blocks of at least this order,
+    /// `100` means no allocation of this order can currently succeed.
+    pub fn fragmentation(&self, order: usize) -> usize {
+        if self.free_frames == 0 {
+            return 0;
+        }
+        let unusable: usize = (0..order.min(ORDERS))
+            .map(|o| self.free_blocks[o] << o)
+            .sum();
+        unusable * 100 / self.free_frames
+    }
+}
+
+impl BuddyFrameAllocator {
+    /// Create a BuddyFrameAllocator from the passed memory map.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that the passed memory map is valid and that
+    /// the complete physical memory is mapped to virtual memory at the passed
+    /// `physical_memory_offset`. The main requirement is that all frames that
+    /// are marked as `USABLE` in the memory map are really unused, so this
+    /// allocator must not be combined with another allocator that hands out
+    /// the same frames.
+    pub unsafe fn init(memory_map: &'static MemoryMap, physical_memory_offset: VirtAddr) -> Self {
+        let max_addr = usable_regions(memory_map)
+            .map(|r| r.1)
+            .max()
+            .unwrap_or(0);
+        let frame_count = (max_addr / FRAME_SIZE) as usize;
+
+        // 每一阶的位图紧挨着存放，第n阶需要 frame_count >> n 个位
+        let mut bitmap_offsets = [0; ORDERS];
+        let mut bits = 0;
+        for (order, offset) in bitmap_offsets.iter_mut().enumerate() {
+            *offset = bits;
+            bits += (frame_count >> order) + 1;
+        }
+        let words = (bits + BITS_PER_WORD - 1) / BITS_PER_WORD;
+        let bitmap_bytes = (words * 8) as u64;
+        let bitmap_bytes_aligned = (bitmap_bytes + FRAME_SIZE - 1) / FRAME_SIZE * FRAME_SIZE;
+
+        let bitmap_start = usable_regions(memory_map)
+            .find(|&(start, end)| end - start >= bitmap_bytes_aligned)
+            .map(|(start, _)| start)
+            .expect("no usable region is large enough for the buddy bitmap");
+        let bitmap_end = bitmap_start + bitmap_bytes_aligned;
+
+        let bitmap_ptr: *mut u64 = (physical_memory_offset + bitmap_start).as_mut_ptr();
+        let bitmap = slice::from_raw_parts_mut(bitmap_ptr, words);
+        for word in bitmap.iter_mut() {
+            *word = 0;
+        }
+
+        let mut allocator = BuddyFrameAllocator {
+            physical_memory_offset,
+            free_lists: [None; ORDERS],
+            free_blocks: [0; ORDERS],
+            bitmap,
+            bitmap_offsets,
+            frame_count,
+            usable_frames: 0,
+        };
+
+        for (start, end) in usable_regions(memory_map) {
+            allocator.usable_frames += ((end - start) / FRAME_SIZE) as usize;
+            // 跳过存放位图的帧
+            if start < bitmap_end && bitmap_start < end {
+                allocator.add_range(start, bitmap_start.max(start));
+                allocator.add_range(bitmap_end.min(end), end);
+            } else {
+                allocator.add_range(start, end);
+            }
+        }
+
+        allocator
+    }
+
+    /// Allocates a block of `2^order` physically contiguous frames.
+    ///
+    /// The returned frame is the first frame of the block and is aligned to the
+    /// size of the block.
+    pub fn allocate(&mut self, order: usize) -> Option<PhysFrame> {
+        if order > MAX_ORDER {
+            return None;
+        }
+        let mut current = (order..ORDERS).find(|&o| self.free_lists[o].is_some())?;
+        let addr = self.free_lists[current].unwrap();
+        unsafe { self.remove(current, addr) };
+
+        // 将多余的部分逐阶拆分，放回对应的空闲链表
+        while current > order {
+            current -= 1;
+            unsafe { self.push(current, addr + (FRAME_SIZE << current)) };
+        }
+        Some(PhysFrame::containing_address(addr))
+    }
+
+    /// Frees a block of `2^order` frames that was returned by `allocate`.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the block was allocated with the same order
+    /// and is no longer in use.
+    pub unsafe fn deallocate(&mut self, frame: PhysFrame, order: usize) {
+        let mut index = frame_index(frame.start_address());
+        assert!(
+            order <= MAX_ORDER && index % (1 << order) == 0,
+            "deallocating misaligned block {:?} of order {}",
+            frame,
+            order
+        );
+        // 块本身或包含它的更高阶的块已经在空闲链表中时是重复释放
+        assert!(
+            index < self.frame_count && (order..ORDERS).all(|o| !self.is_free(o, index)),
+            "deallocating block {:?} of order {} that is not allocated",
+            frame,
+            order
+        );
+
+        // 伙伴空闲时合并为更高一阶的块
+        let mut order = order;
+        while order < MAX_ORDER {
+            let buddy = index ^ (1 << order);
+            if buddy >= self.frame_count || !self.is_free(order, buddy) {
+                break;
+            }
+            self.remove(order, frame_addr(buddy));
+            index &= !(1 << order);
+            order += 1;
+        }
+        self.push(order, frame_addr(index));
+    }
+
+    /// Allocates at least `count` physically contiguous frames.
+    ///
+    /// The request is rounded up to the next power of two, so it must be freed
+    /// through `deallocate_contiguous` with the same `count`.
+    pub fn allocate_contiguous(&mut self, count: usize) -> Option<PhysFrame> {
+        self.allocate(order_for(count))
+    }
+
+    /// Frees a run of frames returned by `allocate_contiguous`.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that `count` matches the allocation and that the
+    /// frames are no longer in use.
+    pub unsafe fn deallocate_contiguous(&mut self, frame: PhysFrame, count: usize) {
+        self.deallocate(frame, order_for(count))
+    }
+
+    /// Returns the number of free blocks per order and the total free frames.
+    pub fn stats(&self) -> BuddyStats {
+        let free_frames = self
+            .free_blocks
+            .iter()
+            .enumerate()
+            .map(|(order, count)| count << order)
+            .sum();
+        BuddyStats {
+            free_blocks: self.free_blocks,
+            free_frames,
+            usable_frames: self.usable_frames,
+        }
+    }
+
+    /// Adds the frames in `[start, end)` as free blocks of the largest
+    /// possible aligned orders.
+    unsafe fn add_range(&mut self, start: u64, end: u64) {
+        let mut index = (start / FRAME_SIZE) as usize;
+        let end = (end / FRAME_SIZE) as usize;
+        while index < end {
+            let mut order = MAX_ORDER;
+            while index % (1 << order) != 0 || index + (1 << order) > end {
+                order -= 1;
+            }
+            self.push(order, frame_addr(index));
+            index += 1 << order;
+        }
+    }
+
+    unsafe fn block(&self, addr: PhysAddr) -> &'static mut FreeBlock {
+        let virt = self.physical_memory_offset + addr.as_u64();
+        &mut *virt.as_mut_ptr()
+    }
+
+    unsafe fn push(&mut self, order: usize, addr: PhysAddr) {
+        let head = self.free_lists[order];
+        *self.block(addr) = FreeBlock { prev: None, next: head };
+        if let Some(head) = head {
+            self.block(head).prev = Some(addr);
+        }
+        self.free_lists[order] = Some(addr);
+        self.free_blocks[order] += 1;
+        self.set_free(order, frame_index(addr), true);
+    }
+
+    unsafe fn remove(&mut self, order: usize, addr: PhysAddr) {
+        let (prev, next) = {
+            let block = self.block(addr);
+            (block.prev, block.next)
+        };
+        match prev {
+            Some(prev) => self.block(prev).next = next,
+            None => self.free_lists[order] = next,
+        }
+        if let Some(next) = next {
+            self.block(next).prev = prev;
+        }
+        self.free_blocks[order] -= 1;
+        self.set_free(order, frame_index(addr), false);
+    }
+
+    fn bit(&self, order: usize, index: usize) -> (usize, u64) {
+        let bit = self.bitmap_offsets[order] + (index >> order);
+        (bit / BITS_PER_WORD, 1 << (bit % BITS_PER_WORD))
+    }
+
+    fn is_free(&self, order: usize, index: usize) -> bool {
+        let (word, mask) = self.bit(order, index);
+        self.bitmap[word] & mask != 0
+    }
+
+    fn set_free(&mut self, order: usize, index: usize, free: bool) {
+        let (word, mask) = self.bit(order, index);
+        if free {
+            self.bitmap[word] |= mask;
+        } else {
+            self.bitmap[word] &= !mask;
+        }
+    }
+}
+
+/// Returns the smallest order whose blocks hold at least `count` frames.
+pub fn order_for(count: usize) -> usize {
+    count.max(1).next_power_of_two().trailing_zeros() as usize
+}
+
+/// Returns the `(start, end)` physical address ranges of the usable regions.
+fn usable_regions(memory_map: &'static MemoryMap) -> impl Iterator<Item = (u64, u64)> {
+    memory_map
+        .iter()
+        .filter(|r| r.region_type == MemoryRegionType::Usable)
+        .map(|r| (r.range.start_addr(), r.range.end_addr()))
+}
+
+fn frame_index(addr: PhysAddr) -> usize {
+    (addr.as_u64() / FRAME_SIZE) as usize
+}
+
+fn frame_addr(index: usize) -> PhysAddr {
+    PhysAddr::new(index as u64 * FRAME_SIZE)
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BuddyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        self.allocate(0)
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BuddyFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.deallocate(frame, 0)
+    }
+}
+
+unsafe impl FrameAllocator<Size2MiB> for BuddyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size2MiB>> {
+        debug_assert_eq!(FRAME_SIZE << HUGE_FRAME_ORDER, Size2MiB::SIZE);
+        self.allocate(HUGE_FRAME_ORDER)
+            .map(|frame| PhysFrame::containing_address(frame.start_address()))
+    }
+}
+
+impl FrameDeallocator<Size2MiB> for BuddyFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size2MiB>) {
+        let frame = PhysFrame::containing_address(frame.start_address());
+        self.deallocate(frame, HUGE_FRAME_ORDER)
+    }
+}
@@ -0,0 +1,169 @@
+use spin::Mutex;
+use x86_64::{
+    registers::control::Cr3,
+    structures::paging::{FrameAllocator, Page, PageTable, PageTableFlags, PhysFrame},
+    VirtAddr,
+};
+use super::address_space::user_entry;
+use super::{phys_to_virt, FRAME_ALLOCATOR};
+
+/// Marks a page that was writable before it was shared by
+/// `AddressSpace::clone_cow`. Bit 9 is ignored by the CPU.
+pub const COPY_ON_WRITE: PageTableFlags = PageTableFlags::BIT_9;
+
+/// Maximum number of frames that can be shared at the same time.
+///
+/// Only shared frames have an entry, a frame without an entry has exactly one
+/// owner. The table is a fixed array so that resolving a page fault never
+/// needs the heap.
+const MAX_SHARED_FRAMES: usize = 8192;
+
+const EMPTY: u64 = u64::MAX;
+
+/// Reference counts of shared frames, an open addressing hash table with
+/// linear probing keyed by the frame address.
+struct RefCounts {
+    frames: [u64; MAX_SHARED_FRAMES],
+    counts: [u32; MAX_SHARED_FRAMES],
+    len: usize,
+}
+
+impl RefCounts {
+    fn slot(&self, frame: u64) -> Result<usize, usize> {
+        let mut index = Self::hash(frame);
+        loop {
+            match self.frames[index] {
+                f if f == frame => return Ok(index),
+                EMPTY => return Err(index),
+                _ => index = (index + 1) % MAX_SHARED_FRAMES,
+            }
+        }
+    }
+
+    fn hash(frame: u64) -> usize {
+        ((frame >> 12).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 32) as usize % MAX_SHARED_FRAMES
+    }
+
+    fn get(&self, frame: u64) -> u32 {
+        self.slot(frame).map_or(1, |index| self.counts[index])
+    }
+
+    fn increment(&mut self, frame: u64) -> Option<u32> {
+        match self.slot(frame) {
+            Ok(index) => {
+                self.counts[index] += 1;
+                Some(self.counts[index])
+            }
+            // 保留一个空位，保证探测总能结束
+            Err(_) if self.len + 1 >= MAX_SHARED_FRAMES => None,
+            Err(index) => {
+                self.frames[index] = frame;
+                self.counts[index] = 2;
+                self.len += 1;
+                Some(2)
+            }
+        }
+    }
+
+    fn decrement(&mut self, frame: u64) -> u32 {
+        let index = match self.slot(frame) {
+            Ok(index) => index,
+            Err(_) => return 0,
+        };
+        self.counts[index] -= 1;
+        let count = self.counts[index];
+        if count == 1 {
+            self.remove(index);
+        }
+        count
+    }
+
+    /// Removes the entry at `index` and moves later entries of the same probe
+    /// sequence back, so that no lookup stops early at the new hole.
+    fn remove(&mut self, mut hole: usize) {
+        self.frames[hole] = EMPTY;
+        self.len -= 1;
+        let mut index = hole;
+        loop {
+            index = (index + 1) % MAX_SHARED_FRAMES;
+            if self.frames[index] == EMPTY {
+                return;
+            }
+            let home = Self::hash(self.frames[index]);
+            let distance = |from: usize, to: usize| (to + MAX_SHARED_FRAMES - from) % MAX_SHARED_FRAMES;
+            if distance(home, index) >= distance(hole, index) {
+                self.frames[hole] = self.frames[index];
+                self.counts[hole] = self.counts[index];
+                self.frames[index] = EMPTY;
+                hole = index;
+            }
+        }
+    }
+}
+
+static REF_COUNTS: Mutex<RefCounts> = Mutex::new(RefCounts {
+    frames: [EMPTY; MAX_SHARED_FRAMES],
+    counts: [0; MAX_SHARED_FRAMES],
+    len: 0,
+});
+
+/// Returns the number of page table entries that map the given frame, or 1
+/// if it is not shared.
+pub fn ref_count(frame: PhysFrame) -> u32 {
+    REF_COUNTS.lock().get(frame.start_address().as_u64())
+}
+
+/// Records another mapping of the frame. Returns `false` if the table of
+/// shared frames is full.
+pub fn share(frame: PhysFrame) -> bool {
+    REF_COUNTS.lock().increment(frame.start_address().as_u64()).is_some()
+}
+
+/// Removes a mapping of the frame and returns whether it was the last one,
+/// i.e. whether the caller has to free the frame.
+pub fn unshare(frame: PhysFrame) -> bool {
+    REF_COUNTS.lock().decrement(frame.start_address().as_u64()) == 0
+}
+
+/// Resolves a write to a copy-on-write page of the active address space.
+///
+/// The page gets a private copy of the frame, or is made writable again if
+/// no other address space maps the frame anymore. Returns `Ok(false)` if the
+/// page is not a copy-on-write page.
+pub fn resolve_write_fault(addr: VirtAddr) -> Result<bool, &'static str> {
+    let page = Page::containing_address(addr);
+    let level_4_table: &mut PageTable =
+        unsafe { &mut *phys_to_virt(Cr3::read().0.start_address()).as_mut_ptr() };
+    let entry = match user_entry(level_4_table, page) {
+        Some(entry) if entry.flags().contains(COPY_ON_WRITE) => entry,
+        _ => return Ok(false),
+    };
+    let old_frame: PhysFrame = PhysFrame::containing_address(entry.addr());
+    let flags = (entry.flags() - COPY_ON_WRITE) | PageTableFlags::WRITABLE;
+
+    let mut ref_counts = REF_COUNTS.lock();
+    if ref_counts.get(old_frame.start_address().as_u64()) == 1 {
+        // 其他地址空间已经不再使用该帧，直接恢复写权限
+        entry.set_flags(flags);
+    } else {
+        // 缺页可能发生在持有帧分配器锁的代码中，等待会永远自旋
+        let new_frame: PhysFrame = FRAME_ALLOCATOR
+            .try_lock()
+            .ok_or("memory::FRAME_ALLOCATOR is held")?
+            .as_mut()
+            .ok_or("memory::init_global has not been called")?
+            .allocate_frame()
+            .ok_or("out of physical memory")?;
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                phys_to_virt(old_frame.start_address()).as_ptr::<u8>(),
+                phys_to_virt(new_frame.start_address()).as_mut_ptr::<u8>(),
+                4096,
+            );
+        }
+        entry.set_addr(new_frame.start_address(), flags);
+        ref_counts.decrement(old_frame.start_address().as_u64());
+    }
+    x86_64::instructions::tlb::flush(page.start_address());
+    Ok(true)
+}
@@ -0,0 +1,200 @@
+use x86_64::{
+    structures::paging::{PageTable, PageTableFlags},
+    PhysAddr, VirtAddr,
+};
+use crate::serial_println;
+use super::MappedPageSize;
+
+/// Flags that the CPU updates on access and that would prevent merging
+/// otherwise identical mappings.
+const VOLATILE_FLAGS: PageTableFlags = PageTableFlags::from_bits_truncate(
+    PageTableFlags::ACCESSED.bits() | PageTableFlags::DIRTY.bits()
+);
+
+/// A run of virtually and physically contiguous pages with the same page size
+/// and flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MappedRange {
+    /// The first virtual address of the range.
+    pub start: VirtAddr,
+    /// The physical address `start` is mapped to.
+    pub phys_start: PhysAddr,
+    /// The length of the range in bytes.
+    pub size: u64,
+    /// The size of the pages that make up the range.
+    pub page_size: MappedPageSize,
+    /// The flags of the leaf entries, without `ACCESSED` and `DIRTY`.
+    pub flags: PageTableFlags,
+}
+
+impl MappedRange {
+    /// Returns the virtual address directly after the range.
+    ///
+    /// This is a plain `u64` because the end of the last range in the lower
+    /// half is not a canonical address.
+    pub fn end_addr(&self) -> u64 {
+        self.start.as_u64().wrapping_add(self.size)
+    }
+
+    /// Returns the number of pages in the range.
+    pub fn page_count(&self) -> u64 {
+        self.size / self.page_size.size()
+    }
+
+    fn try_merge(&mut self, next: &MappedRange) -> bool {
+        let contiguous = self.end_addr() == next.start.as_u64()
+            && self.phys_start.as_u64() + self.size == next.phys_start.as_u64();
+        if contiguous && self.page_size == next.page_size && self.flags == next.flags {
+            self.size += next.size;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Iterates over the leaf entries of a page table hierarchy in address order.
+struct LeafEntries {
+    physical_memory_offset: VirtAddr,
+    tables: [&'static PageTable; 4],
+    indexes: [usize; 4],
+    level: usize,
+}
+
+impl Iterator for LeafEntries {
+    type Item = MappedRange;
+
+    fn next(&mut self) -> Option<MappedRange> {
+        loop {
+            if self.indexes[self.level] == 512 {
+                if self.level == 0 {
+                    return None;
+                }
+                self.level -= 1;
+                self.indexes[self.level] += 1;
+                continue;
+            }
+
+            let entry = &self.tables[self.level][self.indexes[self.level]];
+            let flags = entry.flags();
+            if !flags.contains(PageTableFlags::PRESENT) {
+                self.indexes[self.level] += 1;
+                continue;
+            }
+
+            let page_size = match self.level {
+                1 if flags.contains(PageTableFlags::HUGE_PAGE) => MappedPageSize::Size1GiB,
+                2 if flags.contains(PageTableFlags::HUGE_PAGE) => MappedPageSize::Size2MiB,
+                3 => MappedPageSize::Size4KiB,
+                _ => {
+                    // 进入下一级页表
+                    let virt = self.physical_memory_offset + entry.addr().as_u64();
+                    self.tables[self.level + 1] = unsafe { &*virt.as_ptr() };
+                    self.level += 1;
+                    self.indexes[self.level] = 0;
+                    continue;
+                }
+            };
+
+            let range = MappedRange {
+                start: self.current_addr(),
+                // 大页表项的第12位是PAT位，不属于帧地址
+                phys_start: entry.addr().align_down(page_size.size()),
+                size: page_size.size(),
+                page_size,
+                flags: flags - VOLATILE_FLAGS,
+            };
+            self.indexes[self.level] += 1;
+            return Some(range);
+        }
+    }
+}
+
+impl LeafEntries {
+    /// Returns the virtual address of the entry the walk currently points at.
+    fn current_addr(&self) -> VirtAddr {
+        let addr = self.indexes[..=self.level]
+            .iter()
+            .enumerate()
+            .fold(0u64, |addr, (level, &index)| {
+                addr | (index as u64) << (39 - 9 * level)
+            });
+        // 第47位需要符号扩展到高16位
+        VirtAddr::new(((addr << 16) as i64 >> 16) as u64)
+    }
+}
+
+/// An iterator over all mapped ranges of the active address space.
+///
+/// Created by `mapped_ranges`.
+pub struct MappedRanges {
+    leaves: LeafEntries,
+    pending: Option<MappedRange>,
+}
+
+impl Iterator for MappedRanges {
+    type Item = MappedRange;
+
+    fn next(&mut self) -> Option<MappedRange> {
+        let mut current = self.pending.take().or_else(|| self.leaves.next())?;
+        for next in &mut self.leaves {
+            if !current.try_merge(&next) {
+                self.pending = Some(next);
+                break;
+            }
+        }
+        Some(current)
+    }
+}
+
+/// Returns an iterator over every mapped range of the active level 4 table,
+/// sorted by virtual address.
+///
+/// Adjacent pages are merged into one range when they are contiguous in both
+/// virtual and physical memory and have the same page size and flags.
+///
+/// # Safety
+///
+/// The caller must guarantee that the complete physical memory is mapped to
+/// virtual memory at the passed `physical_memory_offset`. The page tables must
+/// not be modified while the iterator is in use.
+pub unsafe fn mapped_ranges(physical_memory_offset: VirtAddr) -> MappedRanges {
+    let level_4_table: &'static PageTable = super::active_level_4_table(physical_memory_offset);
+    MappedRanges {
+        leaves: LeafEntries {
+            physical_memory_offset,
+            tables: [level_4_table; 4],
+            indexes: [0; 4],
+            level: 0,
+        },
+        pending: None,
+    }
+}
+
+/// Prints every mapped range of the active address space to the serial port.
+///
+/// Every line has the form `virt_start-virt_end -> phys_start size count flags`,
+/// so the output of two boots can be compared with a plain text diff.
+///
+/// # Safety
+///
+/// Same as for `mapped_ranges`.
+pub unsafe fn print_mappings(physical_memory_offset: VirtAddr) {
+    serial_println!("page table dump:");
+    for range in mapped_ranges(physical_memory_offset) {
+        let page_size = match range.page_size {
+            MappedPageSize::Size4KiB => "4KiB",
+            MappedPageSize::Size2MiB => "2MiB",
+            MappedPageSize::Size1GiB => "1GiB",
+        };
+        serial_println!(
+            "{:#018x}-{:#018x} -> {:#014x} {} x{} {:?}",
+            range.start.as_u64(),
+            range.end_addr(),
+            range.phys_start.as_u64(),
+            page_size,
+            range.page_count(),
+            range.flags
+        );
+    }
+}
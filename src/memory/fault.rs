@@ -0,0 +1,230 @@
+use core::fmt;
+use spin::Mutex;
+use x86_64::{
+    structures::{
+        idt::PageFaultErrorCode,
+        paging::{Page, PageTableFlags},
+    },
+    VirtAddr,
+};
+use super::cow;
+use super::vm::{self, VmError};
+
+/// Maximum number of regions that can be registered at the same time.
+///
+/// The regions live in a fixed array so that handling a page fault never
+/// needs the heap, which may itself be the cause of the fault.
+const MAX_FAULT_REGIONS: usize = 32;
+
+/// The kind of memory access that caused a page fault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+/// A page fault as reported by the CPU.
+#[derive(Debug, Clone, Copy)]
+pub struct PageFault {
+    /// The accessed virtual address, read from CR2.
+    pub addr: VirtAddr,
+    /// The error code pushed by the CPU.
+    pub error_code: PageFaultErrorCode,
+    /// The address of the faulting instruction.
+    pub instruction_pointer: VirtAddr,
+}
+
+impl PageFault {
+    /// Returns whether the access was a read, write or instruction fetch.
+    pub fn access(&self) -> Access {
+        if self.error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+            Access::Execute
+        } else if self.error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+            Access::Write
+        } else {
+            Access::Read
+        }
+    }
+
+    /// Returns `true` if the page was present, i.e. the access violated the
+    /// page protection, and `false` if the page was not mapped.
+    pub fn is_protection_violation(&self) -> bool {
+        self.error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION)
+    }
+
+    /// Returns `true` if the access happened in user mode.
+    pub fn is_user_mode(&self) -> bool {
+        self.error_code.contains(PageFaultErrorCode::USER_MODE)
+    }
+}
+
+impl fmt::Display for PageFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Accessed Address: {:?}", self.addr)?;
+        writeln!(
+            f,
+            "Access: {:?} of {} page in {} mode",
+            self.access(),
+            if self.is_protection_violation() { "present" } else { "non-present" },
+            if self.is_user_mode() { "user" } else { "kernel" }
+        )?;
+        writeln!(f, "Instruction Pointer: {:?}", self.instruction_pointer)?;
+        write!(f, "Error Code: {:?}", self.error_code)
+    }
+}
+
+/// A custom page fault handler for a region, see `FaultPolicy::Handler`.
+pub type FaultHandler = fn(&PageFault) -> Result<(), &'static str>;
+
+/// How page faults inside a registered region are handled.
+#[derive(Clone, Copy)]
+pub enum FaultPolicy {
+    /// Map a zeroed frame with the given flags on the first access to a page.
+    LazyAllocate(PageTableFlags),
+    /// Every access is an error, e.g. for guard pages below a stack.
+    Guard,
+    /// Like `Guard`, but the region is the guard page below the stack named
+    /// by the region, so every access is reported as a stack overflow.
+    StackGuard,
+    /// Call the given function, the fault is fatal if it returns an error.
+    Handler(FaultHandler),
+}
+
+/// A virtual memory range with a page fault policy.
+#[derive(Clone, Copy)]
+pub struct FaultRegion {
+    /// A short name used in crash reports.
+    pub name: &'static str,
+    pub start: VirtAddr,
+    /// The length of the region in bytes.
+    pub size: u64,
+    pub policy: FaultPolicy,
+}
+
+impl FaultRegion {
+    /// Returns whether the region contains the given address.
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        addr >= self.start && addr.as_u64() - self.start.as_u64() < self.size
+    }
+
+    fn overlaps(&self, other: &FaultRegion) -> bool {
+        self.start.as_u64() < other.start.as_u64() + other.size
+            && other.start.as_u64() < self.start.as_u64() + self.size
+    }
+}
+
+/// The error returned by `register_region`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterError {
+    /// The region overlaps an already registered region.
+    Overlap,
+    /// All `MAX_FAULT_REGIONS` slots are in use.
+    TableFull,
+}
+
+/// The reason why a page fault could not be handled.
+#[derive(Debug, Clone, Copy)]
+pub enum FaultError {
+    /// The address is not inside any registered region.
+    Unhandled,
+    /// The address is inside a guard region.
+    GuardPage(&'static str),
+    /// The address is inside the guard page of the stack with the given name.
+    StackOverflow(&'static str),
+    /// The region with the given name could not resolve the fault.
+    Region(&'static str, &'static str),
+}
+
+impl fmt::Display for FaultError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FaultError::Unhandled => write!(f, "invalid access outside of any memory region"),
+            FaultError::GuardPage(name) => write!(f, "guard page hit in region `{}`", name),
+            FaultError::StackOverflow(name) => write!(f, "stack overflow on stack {}", name),
+            FaultError::Region(name, reason) => write!(f, "region `{}`: {}", name, reason),
+        }
+    }
+}
+
+const NO_REGION: Option<FaultRegion> = None;
+
+static REGIONS: Mutex<[Option<FaultRegion>; MAX_FAULT_REGIONS]> =
+    Mutex::new([NO_REGION; MAX_FAULT_REGIONS]);
+
+/// Registers a region whose page faults are handled according to its policy.
+pub fn register_region(region: FaultRegion) -> Result<(), RegisterError> {
+    let mut regions = REGIONS.lock();
+    if regions.iter().flatten().any(|r| r.overlaps(&region)) {
+        return Err(RegisterError::Overlap);
+    }
+    let slot = regions
+        .iter_mut()
+        .find(|r| r.is_none())
+        .ok_or(RegisterError::TableFull)?;
+    *slot = Some(region);
+    Ok(())
+}
+
+/// Removes the region starting at `start` and returns it.
+///
+/// Pages that were already mapped for the region stay mapped.
+pub fn unregister_region(start: VirtAddr) -> Option<FaultRegion> {
+    REGIONS
+        .lock()
+        .iter_mut()
+        .find(|r| r.map_or(false, |r| r.start == start))
+        .and_then(|r| r.take())
+}
+
+/// Returns the registered region that contains `addr`.
+pub fn find_region(addr: VirtAddr) -> Option<FaultRegion> {
+    REGIONS.lock().iter().flatten().find(|r| r.contains(addr)).copied()
+}
+
+/// Returns the name of the stack whose guard page contains `addr`.
+pub fn overflowed_stack(addr: VirtAddr) -> Option<&'static str> {
+    match find_region(addr) {
+        Some(FaultRegion { name, policy: FaultPolicy::StackGuard, .. }) => Some(name),
+        _ => None,
+    }
+}
+
+/// Resolves a page fault through the region that contains the faulting address.
+///
+/// Returns `Ok` if the faulting instruction can be restarted and an error that
+/// describes the invalid access otherwise.
+pub fn dispatch(fault: &PageFault) -> Result<(), FaultError> {
+    // 写时复制的页不属于任何区域
+    if fault.access() == Access::Write && fault.is_protection_violation() {
+        let resolved = cow::resolve_write_fault(fault.addr)
+            .map_err(|reason| FaultError::Region("copy-on-write", reason))?;
+        if resolved {
+            return Ok(());
+        }
+    }
+    // 先复制区域信息再释放锁，处理函数可能需要注册新的区域
+    let region = find_region(fault.addr).ok_or(FaultError::Unhandled)?;
+    match region.policy {
+        FaultPolicy::Guard => Err(FaultError::GuardPage(region.name)),
+        FaultPolicy::StackGuard => Err(FaultError::StackOverflow(region.name)),
+        FaultPolicy::LazyAllocate(flags) => {
+            if fault.is_protection_violation() {
+                return Err(FaultError::Region(region.name, "access violates the page flags"));
+            }
+            map_zeroed_page(Page::containing_address(fault.addr), flags)
+                .map_err(|reason| FaultError::Region(region.name, reason))
+        }
+        FaultPolicy::Handler(handler) => {
+            handler(fault).map_err(|reason| FaultError::Region(region.name, reason))
+        }
+    }
+}
+
+/// Maps the given page to a newly allocated, zeroed frame.
+///
+/// Fails instead of waiting if the fault was raised while `memory::MAPPER` or
+/// `memory::FRAME_ALLOCATOR` was held, see `vm::try_map_zeroed_page`.
+pub fn map_zeroed_page(page: Page, flags: PageTableFlags) -> Result<(), &'static str> {
+    vm::try_map_zeroed_page(page, flags).map_err(VmError::as_str)
+}
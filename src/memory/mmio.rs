@@ -0,0 +1,185 @@
+use core::mem::{align_of, size_of};
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::{
+    registers::model_specific::Msr,
+    structures::paging::{Mapper, Page, PageSize, PageTableFlags, PhysFrame, Size4KiB},
+    PhysAddr, VirtAddr,
+};
+use super::vm::{self, RegionKind, VmError, VmRegion};
+use super::MAPPER;
+
+/// The model specific register that holds the page attribute table.
+const IA32_PAT: u32 = 0x277;
+/// The PAT entry used for write-combining mappings, selected by setting only
+/// the PAT bit of a page table entry.
+const PAT_WRITE_COMBINING_ENTRY: u64 = 4;
+const PAT_TYPE_WRITE_COMBINING: u64 = 0x01;
+
+/// For 4KiB pages the PAT bit is at the position of the `HUGE_PAGE` bit.
+const PAT_BIT: PageTableFlags = PageTableFlags::HUGE_PAGE;
+
+static PAT_INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// The memory type of an MMIO mapping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheMode {
+    /// Every access goes to the device, the right choice for registers.
+    Uncached,
+    /// Reads may be cached, writes go to the device immediately.
+    WriteThrough,
+    /// Writes may be combined and reordered, e.g. for frame buffers. Falls
+    /// back to `Uncached` if the CPU has no page attribute table.
+    WriteCombining,
+}
+
+/// A mapped range of device memory, created by `map_mmio`.
+///
+/// All accesses are volatile and checked against the bounds of the range.
+/// The mapping stays until `unmap` is called.
+#[derive(Debug)]
+pub struct Mmio {
+    region: VmRegion,
+    phys: PhysAddr,
+    base: VirtAddr,
+    len: usize,
+}
+
+impl Mmio {
+    /// Returns the virtual address that `phys` was mapped to.
+    pub fn base(&self) -> VirtAddr {
+        self.base
+    }
+
+    /// Returns the physical start address of the range.
+    pub fn phys(&self) -> PhysAddr {
+        self.phys
+    }
+
+    /// Returns the length of the range in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the range is empty, which `map_mmio` never creates.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Reads a `T` at the given byte offset.
+    ///
+    /// Panics if the value is not inside the range or not aligned.
+    pub fn read<T: Copy>(&self, offset: usize) -> T {
+        unsafe { self.ptr::<T>(offset).read_volatile() }
+    }
+
+    /// Writes a `T` at the given byte offset.
+    ///
+    /// Panics if the value is not inside the range or not aligned.
+    pub fn write<T: Copy>(&mut self, offset: usize, value: T) {
+        unsafe { self.ptr::<T>(offset).write_volatile(value) }
+    }
+
+    /// Unmaps the range and releases its virtual address range.
+    pub fn unmap(self) -> Result<(), VmError> {
+        vm::release(self.region.start).map(|_| ())
+    }
+
+    fn ptr<T>(&self, offset: usize) -> *mut T {
+        assert!(
+            offset.checked_add(size_of::<T>()).map_or(false, |end| end <= self.len),
+            "mmio access at offset {:#x} is out of bounds (length {:#x})",
+            offset,
+            self.len
+        );
+        let addr = self.base + offset;
+        assert!(addr.is_aligned(align_of::<T>() as u64), "unaligned mmio access");
+        addr.as_mut_ptr()
+    }
+}
+
+/// Maps `len` bytes of device memory at `phys` as uncached memory.
+///
+/// Requires `memory::init_global`.
+pub fn map_mmio(phys: PhysAddr, len: usize) -> Result<Mmio, VmError> {
+    map_mmio_with(phys, len, CacheMode::Uncached)
+}
+
+/// Maps `len` bytes of device memory at `phys` with the given memory type.
+///
+/// The virtual range is reserved as a `RegionKind::Mmio` region, so the frames
+/// are not returned to the frame allocator when the range is unmapped.
+pub fn map_mmio_with(phys: PhysAddr, len: usize, mode: CacheMode) -> Result<Mmio, VmError> {
+    if len == 0 {
+        return Err(VmError::InvalidRange);
+    }
+    let first_frame = PhysFrame::<Size4KiB>::containing_address(phys);
+    let page_offset = phys - first_frame.start_address();
+    let size = page_offset + len as u64;
+
+    let mut flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let mut use_pat = false;
+    match mode {
+        CacheMode::Uncached => flags |= PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH,
+        CacheMode::WriteThrough => flags |= PageTableFlags::WRITE_THROUGH,
+        CacheMode::WriteCombining if init_pat() => use_pat = true,
+        CacheMode::WriteCombining => {
+            flags |= PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH
+        }
+    }
+
+    let region = vm::reserve("mmio", size, Size4KiB::SIZE, RegionKind::Mmio, flags)?;
+    let start_page = Page::containing_address(region.start);
+    let frames = PhysFrame::range(first_frame, first_frame + region.size / Size4KiB::SIZE);
+    for (page, frame) in Page::range(start_page, start_page + region.size / Size4KiB::SIZE).zip(frames) {
+        if let Err(error) = map_device_page(page, frame, flags, use_pat) {
+            vm::release(region.start)?;
+            return Err(error);
+        }
+    }
+
+    Ok(Mmio {
+        region,
+        phys,
+        base: region.start + page_offset,
+        len,
+    })
+}
+
+fn map_device_page(
+    page: Page,
+    frame: PhysFrame,
+    flags: PageTableFlags,
+    use_pat: bool,
+) -> Result<(), VmError> {
+    unsafe { vm::map_page(page, frame, flags)? };
+    if use_pat {
+        // map_to不接受PAT位（与HUGE_PAGE位相同），映射之后再设置
+        let mut mapper = MAPPER.lock();
+        let mapper = mapper.as_mut().ok_or(VmError::NotInitialized)?;
+        let flags = flags | PageTableFlags::PRESENT | PAT_BIT;
+        unsafe { mapper.update_flags(page, flags) }
+            .map_err(|_| VmError::HugePage)?
+            .flush();
+    }
+    Ok(())
+}
+
+/// Programs the write-combining entry of the page attribute table.
+///
+/// Returns `false` if the CPU has no page attribute table.
+fn init_pat() -> bool {
+    if PAT_INITIALIZED.load(Ordering::SeqCst) {
+        return true;
+    }
+    if !crate::cpu::features().pat {
+        return false;
+    }
+    let shift = PAT_WRITE_COMBINING_ENTRY * 8;
+    unsafe {
+        let mut pat = Msr::new(IA32_PAT);
+        let value = pat.read() & !(0xff << shift);
+        pat.write(value | (PAT_TYPE_WRITE_COMBINING << shift));
+    }
+    PAT_INITIALIZED.store(true, Ordering::SeqCst);
+    true
+}
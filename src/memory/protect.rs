@@ -0,0 +1,91 @@
+use alloc::vec::Vec;
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use x86_64::{
+    registers::{
+        control::{Cr0, Cr0Flags},
+        model_specific::{Efer, EferFlags},
+    },
+    structures::paging::{Mapper, Page, PageSize, PageTableFlags, Size1GiB, Size2MiB, Size4KiB},
+    PhysAddr, VirtAddr,
+};
+use super::{mapped_ranges, physical_memory_offset, MappedPageSize, MappedRange, MAPPER};
+
+/// Enables the `NO_EXECUTE` page table flag (EFER.NXE) and makes read-only
+/// pages read-only for the kernel as well (CR0.WP).
+///
+/// Without NXE the `NO_EXECUTE` bit is reserved and every page that sets it
+/// causes a page fault, so this must run before such pages are mapped.
+pub fn enable_protection() {
+    unsafe {
+        Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE));
+        Cr0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT));
+    }
+}
+
+/// Enforces W^X for the mappings the bootloader created.
+///
+/// Executable pages of the kernel image are made read-only, all other pages
+/// of the kernel image, the boot stack, the boot info and the physical memory
+/// window are marked `NO_EXECUTE`. Returns the number of updated pages.
+///
+/// Requires `memory::init_global` and the heap.
+pub fn protect_kernel(memory_map: &MemoryMap) -> usize {
+    let offset = physical_memory_offset();
+    let window_end = offset + memory_map.iter().map(|r| r.range.end_addr()).max().unwrap_or(0);
+    let region_type = |addr: PhysAddr| {
+        memory_map
+            .iter()
+            .find(|r| r.range.start_addr() <= addr.as_u64() && addr.as_u64() < r.range.end_addr())
+            .map(|r| r.region_type)
+    };
+
+    // 先收集所有映射，遍历页表时不能修改页表
+    let ranges: Vec<MappedRange> = unsafe { mapped_ranges(offset) }.collect();
+
+    let mut mapper = MAPPER.lock();
+    let mapper = mapper.as_mut().expect("memory::init_global has not been called");
+    let mut updated = 0;
+    for range in ranges {
+        let executable = !range.flags.contains(PageTableFlags::NO_EXECUTE);
+        let flags = if range.start >= offset && range.start < window_end {
+            range.flags | PageTableFlags::NO_EXECUTE
+        } else {
+            // 按帧的类型分类，大页的phys_start已经去掉了表项中的PAT位
+            match region_type(range.phys_start) {
+                Some(MemoryRegionType::Kernel) if executable => range.flags - PageTableFlags::WRITABLE,
+                Some(MemoryRegionType::Kernel)
+                | Some(MemoryRegionType::KernelStack)
+                | Some(MemoryRegionType::BootInfo) => range.flags | PageTableFlags::NO_EXECUTE,
+                _ => continue,
+            }
+        };
+        if flags == range.flags {
+            continue;
+        }
+        updated += match range.page_size {
+            MappedPageSize::Size4KiB => update_range::<Size4KiB, _>(mapper, &range, flags),
+            MappedPageSize::Size2MiB => update_range::<Size2MiB, _>(mapper, &range, flags),
+            MappedPageSize::Size1GiB => update_range::<Size1GiB, _>(mapper, &range, flags),
+        };
+    }
+    x86_64::instructions::tlb::flush_all();
+    updated
+}
+
+fn update_range<S: PageSize, M: Mapper<S>>(
+    mapper: &mut M,
+    range: &MappedRange,
+    flags: PageTableFlags,
+) -> usize {
+    let start = Page::<S>::containing_address(range.start);
+    let end = Page::<S>::containing_address(VirtAddr::new(range.end_addr() - 1));
+    let mut updated = 0;
+    for page in Page::range_inclusive(start, end) {
+        // 最后统一刷新TLB
+        if let Ok(flush) = unsafe { mapper.update_flags(page, flags) } {
+            flush.ignore();
+            updated += 1;
+        }
+    }
+    updated
+}
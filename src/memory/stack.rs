@@ -0,0 +1,115 @@
+use x86_64::{
+    structures::paging::{Page, PageSize, PageTableFlags, Size4KiB},
+    VirtAddr,
+};
+use super::fault::{self, FaultPolicy, FaultRegion, RegisterError};
+use super::vm::{self, RegionKind, VmError};
+use super::{physical_memory_offset, translate_addr};
+
+/// The upper bound for the number of pages `register_current_stack` inspects.
+const MAX_STACK_PAGES: u64 = 4096;
+
+/// A kernel stack with an unmapped guard page directly below it.
+#[derive(Debug, Clone, Copy)]
+pub struct KernelStack {
+    /// The name reported when the stack overflows.
+    pub name: &'static str,
+    /// The unmapped page below the stack.
+    pub guard_page: Page,
+    /// The lowest address of the stack.
+    pub bottom: VirtAddr,
+    /// The address directly above the stack, i.e. the initial stack pointer.
+    pub top: VirtAddr,
+}
+
+/// The error returned by the stack functions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackError {
+    /// The stack could not be reserved or mapped.
+    Vm(VmError),
+    /// The guard page could not be registered.
+    Register(RegisterError),
+    /// The current stack has no unmapped page below it.
+    NoGuardPage,
+}
+
+/// Allocates a stack of `pages` mapped pages below an unmapped guard page.
+///
+/// An access to the guard page is reported as a stack overflow on the stack
+/// with the given name. Requires `memory::init_global`.
+pub fn allocate_stack(name: &'static str, pages: u64) -> Result<KernelStack, StackError> {
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let size = (pages + 1) * Size4KiB::SIZE;
+    let region = vm::reserve(name, size, Size4KiB::SIZE, RegionKind::Stack, flags)
+        .map_err(StackError::Vm)?;
+
+    let guard_page = Page::containing_address(region.start);
+    let result = register_guard(name, guard_page)
+        .map_err(StackError::Register)
+        .and_then(|()| {
+            for page in Page::range(guard_page + 1, guard_page + 1 + pages) {
+                vm::map_zeroed_page(page, flags).map_err(StackError::Vm)?;
+            }
+            Ok(())
+        });
+    if let Err(error) = result {
+        fault::unregister_region(guard_page.start_address());
+        vm::release(region.start).map_err(StackError::Vm)?;
+        return Err(error);
+    }
+
+    Ok(KernelStack {
+        name,
+        guard_page,
+        bottom: (guard_page + 1).start_address(),
+        top: region.end(),
+    })
+}
+
+/// Registers the unmapped page below the stack that is currently in use as
+/// the guard page of a stack with the given name.
+///
+/// This is used for the boot stack, which the bootloader maps with an
+/// unmapped page below it. The stack is also reserved as a region of the
+/// kernel address space. Requires `memory::init`.
+pub fn register_current_stack(name: &'static str) -> Result<KernelStack, StackError> {
+    let marker = 0u8;
+    let current = Page::containing_address(VirtAddr::from_ptr(&marker));
+    let is_mapped = |page: Page| unsafe {
+        translate_addr(page.start_address(), physical_memory_offset()).is_some()
+    };
+
+    let mut bottom = current;
+    while is_mapped(bottom - 1) {
+        bottom -= 1;
+        if current - bottom > MAX_STACK_PAGES {
+            return Err(StackError::NoGuardPage);
+        }
+    }
+    let mut top = current;
+    while top - current < MAX_STACK_PAGES && is_mapped(top + 1) {
+        top += 1;
+    }
+
+    let guard_page = bottom - 1;
+    let size = (top + 1).start_address() - guard_page.start_address();
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    vm::reserve_fixed(name, guard_page.start_address(), size, RegionKind::Stack, flags)
+        .map_err(StackError::Vm)?;
+    register_guard(name, guard_page).map_err(StackError::Register)?;
+    Ok(KernelStack {
+        name,
+        guard_page,
+        bottom: bottom.start_address(),
+        top: (top + 1).start_address(),
+    })
+}
+
+fn register_guard(name: &'static str, guard_page: Page) -> Result<(), RegisterError> {
+    fault::register_region(FaultRegion {
+        name,
+        start: guard_page.start_address(),
+        size: guard_page.size(),
+        policy: FaultPolicy::StackGuard,
+    })
+}
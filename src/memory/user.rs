@@ -0,0 +1,106 @@
+use core::fmt;
+use x86_64::VirtAddr;
+use crate::cpu;
+
+/// Start of the part of the address space that belongs to user programs.
+pub const USER_START: u64 = 0x_0000_1000_0000_0000;
+/// End (exclusive) of the part of the address space that belongs to user programs.
+pub const USER_END: u64 = 0x_0000_2000_0000_0000;
+
+/// The error returned by `copy_from_user` and `copy_to_user`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserCopyError {
+    /// The range is not completely inside the user part of the address space.
+    BadAddress,
+    /// A page of the range is not mapped or not accessible.
+    Fault,
+}
+
+impl fmt::Display for UserCopyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UserCopyError::BadAddress => write!(f, "address outside of user memory"),
+            UserCopyError::Fault => write!(f, "page fault while accessing user memory"),
+        }
+    }
+}
+
+// `copy_user_bytes(dst, src, len)`返回未拷贝的字节数。
+// `rep movsb`触发缺页时，缺页处理函数将返回地址改为`copy_user_fixup`，
+// 此时rcx中是剩余的字节数。
+global_asm!(
+    r#"
+    .global copy_user_bytes
+    .global copy_user_insn
+    .global copy_user_fixup
+copy_user_bytes:
+    mov %rdx, %rcx
+copy_user_insn:
+    rep movsb
+    xor %eax, %eax
+    ret
+copy_user_fixup:
+    mov %rcx, %rax
+    ret
+"#
+);
+
+extern "C" {
+    fn copy_user_bytes(dst: *mut u8, src: *const u8, len: usize) -> usize;
+    static copy_user_insn: u8;
+    static copy_user_fixup: u8;
+}
+
+/// Returns whether `len` bytes starting at `addr` are inside the user part of
+/// the address space.
+pub fn is_user_range(addr: VirtAddr, len: usize) -> bool {
+    let start = addr.as_u64();
+    start >= USER_START && start.checked_add(len as u64).map_or(false, |end| end <= USER_END)
+}
+
+/// Copies `dst.len()` bytes from user memory at `src` into `dst`.
+pub fn copy_from_user(dst: &mut [u8], src: VirtAddr) -> Result<(), UserCopyError> {
+    if !is_user_range(src, dst.len()) {
+        return Err(UserCopyError::BadAddress);
+    }
+    unsafe { copy_user(dst.as_mut_ptr(), src.as_ptr(), dst.len()) }
+}
+
+/// Copies `src` into user memory at `dst`.
+pub fn copy_to_user(dst: VirtAddr, src: &[u8]) -> Result<(), UserCopyError> {
+    if !is_user_range(dst, src.len()) {
+        return Err(UserCopyError::BadAddress);
+    }
+    unsafe { copy_user(dst.as_mut_ptr(), src.as_ptr(), src.len()) }
+}
+
+/// Returns the address at which a page fault at `instruction_pointer` and
+/// `addr` continues, if the fault happened while copying user memory.
+///
+/// The page fault handler calls this for faults that no region handles, so
+/// that the copy functions return `UserCopyError::Fault` instead of panicking.
+pub fn fixup_address(instruction_pointer: VirtAddr, addr: VirtAddr) -> Option<VirtAddr> {
+    let insn = unsafe { VirtAddr::from_ptr(&copy_user_insn) };
+    if instruction_pointer == insn && is_user_range(addr, 1) {
+        Some(unsafe { VirtAddr::from_ptr(&copy_user_fixup) })
+    } else {
+        None
+    }
+}
+
+unsafe fn copy_user(dst: *mut u8, src: *const u8, len: usize) -> Result<(), UserCopyError> {
+    // 开启SMAP时内核只能在设置了RFLAGS.AC时访问用户页
+    let smap = cpu::smap_enabled();
+    if smap {
+        asm!("stac");
+    }
+    let remaining = copy_user_bytes(dst, src, len);
+    if smap {
+        asm!("clac");
+    }
+    if remaining == 0 {
+        Ok(())
+    } else {
+        Err(UserCopyError::Fault)
+    }
+}
@@ -0,0 +1,360 @@
+use bootloader::bootinfo::MemoryMap;
+use core::fmt;
+use spin::Mutex;
+use x86_64::{
+    structures::paging::{
+        mapper::{MapToError, UnmapError},
+        FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize, PageTableFlags,
+        PhysFrame, Size4KiB,
+    },
+    VirtAddr,
+};
+use super::{phys_to_virt, physical_memory_offset, BitmapFrameAllocator, FRAME_ALLOCATOR, MAPPER};
+
+/// Start of the area from which `reserve` and `allocate` hand out ranges.
+pub const VM_AREA_START: u64 = 0x_6000_0000_0000;
+/// End (exclusive) of the area from which `reserve` and `allocate` hand out ranges.
+pub const VM_AREA_END: u64 = 0x_7000_0000_0000;
+
+/// Maximum number of regions that can be reserved at the same time.
+///
+/// Like the page fault regions, the regions live in a fixed array so that
+/// reserving virtual memory never needs the heap.
+const MAX_VM_REGIONS: usize = 64;
+
+/// What a region of the kernel address space is used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    Heap,
+    Stack,
+    /// Device memory, the mapped frames do not belong to the frame allocator.
+    Mmio,
+    /// The window in which the bootloader mapped the complete physical memory.
+    PhysicalMemory,
+    Other,
+}
+
+impl RegionKind {
+    /// Returns whether the frames mapped in a region of this kind come from
+    /// the frame allocator and are freed when the region is released.
+    pub fn owns_frames(self) -> bool {
+        !matches!(self, RegionKind::Mmio | RegionKind::PhysicalMemory)
+    }
+}
+
+/// A reserved range of the kernel address space.
+#[derive(Debug, Clone, Copy)]
+pub struct VmRegion {
+    /// A short name used in error messages and dumps.
+    pub name: &'static str,
+    pub start: VirtAddr,
+    /// The length of the region in bytes, always a multiple of the page size.
+    pub size: u64,
+    pub kind: RegionKind,
+    /// The flags the pages of the region are mapped with, without `PRESENT`.
+    pub flags: PageTableFlags,
+}
+
+impl VmRegion {
+    /// Returns the address directly after the region.
+    pub fn end(&self) -> VirtAddr {
+        self.start + self.size
+    }
+
+    /// Returns whether the region contains the given address.
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        addr >= self.start && addr.as_u64() - self.start.as_u64() < self.size
+    }
+
+    /// Returns the pages of the region.
+    pub fn pages(&self) -> impl Iterator<Item = Page> {
+        let start = Page::containing_address(self.start);
+        Page::range(start, start + self.size / Size4KiB::SIZE)
+    }
+
+    fn overlaps(&self, start: u64, size: u64) -> bool {
+        self.start.as_u64() < start + size && start < self.start.as_u64() + self.size
+    }
+}
+
+/// The error returned by the functions of this module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmError {
+    /// The size is zero, the start is not page aligned or the alignment is
+    /// not a power of two.
+    InvalidRange,
+    /// The range overlaps an already reserved region.
+    Overlap,
+    /// The allocation area has no free range that is large enough.
+    OutOfVirtualMemory,
+    /// All `MAX_VM_REGIONS` slots are in use.
+    TableFull,
+    /// No region starts at the given address.
+    NotFound,
+    /// No physical frame is available.
+    OutOfMemory,
+    /// The page is already mapped.
+    AlreadyMapped,
+    /// The page lies inside a huge page.
+    HugePage,
+    /// `memory::init_global` has not been called.
+    NotInitialized,
+    /// `memory::MAPPER` or `memory::FRAME_ALLOCATOR` is held, e.g. by the code
+    /// that caused a page fault.
+    Locked,
+}
+
+impl VmError {
+    /// Returns a short description of the error.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            VmError::InvalidRange => "invalid range or alignment",
+            VmError::Overlap => "range overlaps a reserved region",
+            VmError::OutOfVirtualMemory => "out of virtual memory",
+            VmError::TableFull => "too many reserved regions",
+            VmError::NotFound => "no region starts at this address",
+            VmError::OutOfMemory => "out of physical memory",
+            VmError::AlreadyMapped => "page already mapped",
+            VmError::HugePage => "page is part of a huge page",
+            VmError::NotInitialized => "memory::init_global has not been called",
+            VmError::Locked => "memory::MAPPER or memory::FRAME_ALLOCATOR is held",
+        }
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<MapToError<Size4KiB>> for VmError {
+    fn from(error: MapToError<Size4KiB>) -> Self {
+        match error {
+            MapToError::FrameAllocationFailed => VmError::OutOfMemory,
+            MapToError::ParentEntryHugePage => VmError::HugePage,
+            MapToError::PageAlreadyMapped(_) => VmError::AlreadyMapped,
+        }
+    }
+}
+
+const NO_REGION: Option<VmRegion> = None;
+
+static REGIONS: Mutex<[Option<VmRegion>; MAX_VM_REGIONS]> =
+    Mutex::new([NO_REGION; MAX_VM_REGIONS]);
+
+/// Reserves the window in which the bootloader mapped the physical memory, so
+/// that no other region can be placed there.
+///
+/// Requires `memory::init`.
+pub fn init(memory_map: &MemoryMap) -> Result<VmRegion, VmError> {
+    let end = memory_map.iter().map(|r| r.range.end_addr()).max().unwrap_or(0);
+    let size = align_up(end, Size4KiB::SIZE);
+    reserve_fixed(
+        "physical memory",
+        physical_memory_offset(),
+        size,
+        RegionKind::PhysicalMemory,
+        PageTableFlags::WRITABLE,
+    )
+}
+
+/// Reserves the range starting at `start`, e.g. for a window that is mapped
+/// at a fixed address. Nothing is mapped.
+pub fn reserve_fixed(
+    name: &'static str,
+    start: VirtAddr,
+    size: u64,
+    kind: RegionKind,
+    flags: PageTableFlags,
+) -> Result<VmRegion, VmError> {
+    if size == 0 || !start.is_aligned(Size4KiB::SIZE) {
+        return Err(VmError::InvalidRange);
+    }
+    let size = align_up(size, Size4KiB::SIZE);
+    let mut regions = REGIONS.lock();
+    if regions.iter().flatten().any(|r| r.overlaps(start.as_u64(), size)) {
+        return Err(VmError::Overlap);
+    }
+    insert(&mut regions, VmRegion { name, start, size, kind, flags })
+}
+
+/// Reserves a free range of `size` bytes in the allocation area whose start
+/// is aligned to `align`. Nothing is mapped.
+pub fn reserve(
+    name: &'static str,
+    size: u64,
+    align: u64,
+    kind: RegionKind,
+    flags: PageTableFlags,
+) -> Result<VmRegion, VmError> {
+    if size == 0 || !align.is_power_of_two() {
+        return Err(VmError::InvalidRange);
+    }
+    let size = align_up(size, Size4KiB::SIZE);
+    let align = align.max(Size4KiB::SIZE);
+
+    let mut regions = REGIONS.lock();
+    // first fit: 跳过与候选范围重叠的区域，直到找到空闲的范围
+    let mut start = align_up(VM_AREA_START, align);
+    while let Some(region) = regions.iter().flatten().find(|r| r.overlaps(start, size)) {
+        start = align_up(region.end().as_u64(), align);
+    }
+    if start + size > VM_AREA_END {
+        return Err(VmError::OutOfVirtualMemory);
+    }
+    insert(&mut regions, VmRegion { name, start: VirtAddr::new(start), size, kind, flags })
+}
+
+/// Reserves a range like `reserve` and maps every page of it to a newly
+/// allocated, zeroed frame.
+///
+/// Requires `memory::init_global`.
+pub fn allocate(
+    name: &'static str,
+    size: u64,
+    align: u64,
+    kind: RegionKind,
+    flags: PageTableFlags,
+) -> Result<VmRegion, VmError> {
+    let region = reserve(name, size, align, kind, flags)?;
+    for page in region.pages() {
+        if let Err(error) = map_zeroed_page(page, flags) {
+            release(region.start)?;
+            return Err(error);
+        }
+    }
+    Ok(region)
+}
+
+/// Removes the region starting at `start`, unmaps its pages and returns it.
+///
+/// The frames of the region are freed unless the frames of its kind are not
+/// owned by the frame allocator, see `RegionKind::owns_frames`.
+pub fn release(start: VirtAddr) -> Result<VmRegion, VmError> {
+    let region = REGIONS
+        .lock()
+        .iter_mut()
+        .find(|r| r.map_or(false, |r| r.start == start))
+        .and_then(|r| r.take())
+        .ok_or(VmError::NotFound)?;
+
+    let mut mapper = MAPPER.lock();
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    let (mapper, frame_allocator) = match (mapper.as_mut(), frame_allocator.as_mut()) {
+        (Some(mapper), Some(frame_allocator)) => (mapper, frame_allocator),
+        // 没有映射器时区域中不可能有映射的页
+        _ => return Ok(region),
+    };
+    for page in region.pages() {
+        if region.kind == RegionKind::Mmio {
+            // 写合并页的P1表项设置了PAT位，它与HUGE_PAGE位相同，unmap会把它当作大页拒绝，先清除
+            if let Ok(flush) = unsafe { mapper.update_flags(page, region.flags | PageTableFlags::PRESENT) } {
+                flush.ignore();
+            }
+        }
+        match mapper.unmap(page) {
+            Ok((frame, flush)) => {
+                flush.flush();
+                if region.kind.owns_frames() {
+                    unsafe { frame_allocator.deallocate_frame(frame) };
+                }
+            }
+            Err(UnmapError::PageNotMapped) => {}
+            Err(error) => panic!("failed to unmap {:?} of region `{}`: {:?}", page, region.name, error),
+        }
+    }
+    Ok(region)
+}
+
+/// Returns the reserved region that contains `addr`.
+pub fn find(addr: VirtAddr) -> Option<VmRegion> {
+    REGIONS.lock().iter().flatten().find(|r| r.contains(addr)).copied()
+}
+
+/// Maps the given page to `frame`. Fails with `VmError::AlreadyMapped`
+/// instead of replacing an existing mapping.
+///
+/// # Safety
+///
+/// The caller must guarantee that the frame is not used otherwise, e.g.
+/// through another mapping.
+pub unsafe fn map_page(page: Page, frame: PhysFrame, flags: PageTableFlags) -> Result<(), VmError> {
+    let mut mapper = MAPPER.lock();
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    match (mapper.as_mut(), frame_allocator.as_mut()) {
+        (Some(mapper), Some(frame_allocator)) => {
+            let flags = flags | PageTableFlags::PRESENT;
+            mapper.map_to(page, frame, flags, frame_allocator)?.flush();
+            Ok(())
+        }
+        _ => Err(VmError::NotInitialized),
+    }
+}
+
+/// Maps the given page to a newly allocated, zeroed frame.
+pub fn map_zeroed_page(page: Page, flags: PageTableFlags) -> Result<(), VmError> {
+    let mut mapper = MAPPER.lock();
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    match (mapper.as_mut(), frame_allocator.as_mut()) {
+        (Some(mapper), Some(frame_allocator)) => map_zeroed_page_with(mapper, frame_allocator, page, flags),
+        _ => Err(VmError::NotInitialized),
+    }
+}
+
+/// Like `map_zeroed_page`, but fails with `VmError::Locked` instead of
+/// waiting for `memory::MAPPER` or `memory::FRAME_ALLOCATOR`.
+///
+/// Used by the page fault handler: a fault raised while one of the locks is
+/// held would otherwise spin forever.
+pub fn try_map_zeroed_page(page: Page, flags: PageTableFlags) -> Result<(), VmError> {
+    let (mut mapper, mut frame_allocator) = match (MAPPER.try_lock(), FRAME_ALLOCATOR.try_lock()) {
+        (Some(mapper), Some(frame_allocator)) => (mapper, frame_allocator),
+        _ => return Err(VmError::Locked),
+    };
+    match (mapper.as_mut(), frame_allocator.as_mut()) {
+        (Some(mapper), Some(frame_allocator)) => map_zeroed_page_with(mapper, frame_allocator, page, flags),
+        _ => Err(VmError::NotInitialized),
+    }
+}
+
+fn map_zeroed_page_with(
+    mapper: &mut OffsetPageTable<'static>,
+    frame_allocator: &mut BitmapFrameAllocator,
+    page: Page,
+    flags: PageTableFlags,
+) -> Result<(), VmError> {
+    let frame: PhysFrame = frame_allocator.allocate_frame().ok_or(VmError::OutOfMemory)?;
+    // 通过物理内存偏移映射清零，页本身可能不可写
+    unsafe {
+        let ptr: *mut u8 = phys_to_virt(frame.start_address()).as_mut_ptr();
+        core::ptr::write_bytes(ptr, 0, Size4KiB::SIZE as usize);
+    }
+    let flags = flags | PageTableFlags::PRESENT;
+    match unsafe { mapper.map_to(page, frame, flags, frame_allocator) } {
+        Ok(flush) => {
+            flush.flush();
+            Ok(())
+        }
+        Err(error) => {
+            unsafe { frame_allocator.deallocate_frame(frame) };
+            Err(error.into())
+        }
+    }
+}
+
+fn insert(
+    regions: &mut [Option<VmRegion>; MAX_VM_REGIONS],
+    region: VmRegion,
+) -> Result<VmRegion, VmError> {
+    let slot = regions
+        .iter_mut()
+        .find(|r| r.is_none())
+        .ok_or(VmError::TableFull)?;
+    *slot = Some(region);
+    Ok(region)
+}
+
+fn align_up(addr: u64, align: u64) -> u64 {
+    (addr + align - 1) & !(align - 1)
+}
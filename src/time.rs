@@ -0,0 +1,115 @@
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use core::time::Duration;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+use crate::print;
+
+mod lapic;
+pub mod rtc;
+pub mod timer;
+mod tsc;
+
+pub use self::rtc::DateTime;
+pub use self::lapic::{start_timer, stop_timer, TimerError};
+pub use self::tsc::{
+    calibrate, calibration, duration_to_tsc, monotonic, nanos, read_tsc, Calibration, CALIBRATION_WINDOW,
+};
+pub use crate::interrupts::apic::TimerMode;
+
+/// The input clock of the programmable interval timer in Hz.
+pub const PIT_FREQUENCY: u32 = 1_193_182;
+
+/// The timer interrupt frequency `os_626::init` programs the PIT to.
+pub const DEFAULT_FREQUENCY: u32 = 100;
+
+/// The interval at which the heartbeat prints a dot, see `set_heartbeat`.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+const PIT_CHANNEL_0: u16 = 0x40;
+const PIT_COMMAND: u16 = 0x43;
+/// Channel 0, lobyte/hibyte access, mode 2 (rate generator), binary.
+const PIT_RATE_GENERATOR: u8 = 0b0011_0100;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+// 每次时钟中断累加当前的周期，修改频率后已经过去的时间保持不变
+static UPTIME_NANOS: AtomicU64 = AtomicU64::new(0);
+static TICK_NANOS: AtomicU64 = AtomicU64::new(0);
+static FREQUENCY: AtomicU32 = AtomicU32::new(0);
+static HEARTBEAT: AtomicBool = AtomicBool::new(false);
+static NEXT_HEARTBEAT: AtomicU64 = AtomicU64::new(0);
+
+// 端口访问必须成对进行，防止两次配置交错
+static PIT: Mutex<()> = Mutex::new(());
+
+/// Programs channel 0 of the PIT to raise the timer interrupt `hz` times per
+/// second and returns the actual frequency, which differs slightly because
+/// the PIT divides its input clock by an integer between 1 and 65536.
+pub fn set_frequency(hz: u32) -> u32 {
+    let divisor = (PIT_FREQUENCY + hz / 2) / hz.max(1);
+    let divisor = divisor.max(1).min(65536);
+    without_interrupts(|| {
+        let _pit = PIT.lock();
+        let mut command = Port::<u8>::new(PIT_COMMAND);
+        let mut data = Port::<u8>::new(PIT_CHANNEL_0);
+        // 65536对应的重装值是0
+        let reload = divisor as u16;
+        unsafe {
+            command.write(PIT_RATE_GENERATOR);
+            data.write(reload as u8);
+            data.write((reload >> 8) as u8);
+        }
+        TICK_NANOS.store(u64::from(divisor) * 1_000_000_000 / u64::from(PIT_FREQUENCY), Ordering::SeqCst);
+        FREQUENCY.store((PIT_FREQUENCY + divisor / 2) / divisor, Ordering::SeqCst);
+    });
+    frequency()
+}
+
+/// Returns the timer interrupt frequency in Hz, or 0 before `set_frequency`.
+pub fn frequency() -> u32 {
+    FREQUENCY.load(Ordering::SeqCst)
+}
+
+/// Counts a timer interrupt, called by the timer interrupt handler.
+pub fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+    let now = UPTIME_NANOS.fetch_add(TICK_NANOS.load(Ordering::Relaxed), Ordering::SeqCst);
+    if HEARTBEAT.load(Ordering::Relaxed) && now >= NEXT_HEARTBEAT.load(Ordering::Relaxed) {
+        NEXT_HEARTBEAT.store(now + HEARTBEAT_INTERVAL.as_nanos() as u64, Ordering::Relaxed);
+        print!(".");
+    }
+}
+
+/// Returns the number of timer interrupts since boot.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Returns the time since the timer interrupt was enabled, with the
+/// resolution of one timer tick.
+pub fn uptime() -> Duration {
+    Duration::from_nanos(UPTIME_NANOS.load(Ordering::SeqCst))
+}
+
+/// Waits until at least `duration` has passed.
+///
+/// The CPU halts between timer interrupts, so interrupts must be enabled.
+/// Expired timers run while waiting, see `timer::run_expired`.
+pub fn sleep(duration: Duration) {
+    debug_assert!(
+        x86_64::instructions::interrupts::are_enabled(),
+        "time::sleep with interrupts disabled never returns"
+    );
+    let end = uptime() + duration;
+    while uptime() < end {
+        x86_64::instructions::hlt();
+        timer::run_expired();
+    }
+}
+
+/// Enables or disables printing a dot every `HEARTBEAT_INTERVAL`, which
+/// shows that timer interrupts arrive. Disabled by default.
+pub fn set_heartbeat(enabled: bool) {
+    NEXT_HEARTBEAT.store(0, Ordering::Relaxed);
+    HEARTBEAT.store(enabled, Ordering::Relaxed);
+}
@@ -0,0 +1,80 @@
+use core::fmt;
+use core::time::Duration;
+use x86_64::instructions::interrupts::without_interrupts;
+use crate::cpu;
+use crate::interrupts::{self, apic::{self, TimerMode}};
+use super::tsc::{calibration, duration_to_tsc, read_tsc};
+
+/// An error starting the local APIC timer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerError {
+    /// The local APIC is not enabled, see `interrupts::enable_apic`.
+    ApicDisabled,
+    /// `calibrate` did not measure the frequency of the local APIC timer.
+    NotCalibrated,
+    /// CPUID does not report the TSC-deadline mode.
+    TscDeadlineUnsupported,
+    /// The duration does not fit into the 32 bit count of the timer.
+    OutOfRange,
+}
+
+impl fmt::Display for TimerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TimerError::ApicDisabled => write!(f, "the local apic is not enabled"),
+            TimerError::NotCalibrated => write!(f, "the local apic timer is not calibrated"),
+            TimerError::TscDeadlineUnsupported => write!(f, "the cpu does not support the tsc-deadline mode"),
+            TimerError::OutOfRange => write!(f, "the duration is out of range for the local apic timer"),
+        }
+    }
+}
+
+/// Starts the local APIC timer, which raises `apic::TIMER_VECTOR` after
+/// `duration`, and in `TimerMode::Periodic` every `duration` from then on.
+/// Install a handler with `apic::set_timer_handler`.
+///
+/// The timer must be calibrated with `calibrate`. Starting it again replaces
+/// the previous mode and duration.
+pub fn start_timer(mode: TimerMode, duration: Duration) -> Result<(), TimerError> {
+    if !interrupts::apic_enabled() {
+        return Err(TimerError::ApicDisabled);
+    }
+    let calibration = calibration().ok_or(TimerError::NotCalibrated)?;
+    let count = match mode {
+        TimerMode::TscDeadline => {
+            if !cpu::features().tsc_deadline {
+                return Err(TimerError::TscDeadlineUnsupported);
+            }
+            0
+        }
+        TimerMode::OneShot | TimerMode::Periodic => {
+            let hz = calibration.apic_timer_hz.ok_or(TimerError::NotCalibrated)?;
+            let count = duration.as_nanos() * u128::from(hz) / 1_000_000_000;
+            // 计数为0会停止定时器
+            if count > u128::from(u32::MAX) {
+                return Err(TimerError::OutOfRange);
+            }
+            (count as u32).max(1)
+        }
+    };
+    without_interrupts(|| {
+        let mut local_apic = apic::LOCAL_APIC.lock();
+        let local = local_apic.as_mut().ok_or(TimerError::ApicDisabled)?;
+        local.start_timer(mode, count, false);
+        if mode == TimerMode::TscDeadline {
+            // 必须先切换LVT的模式再写入截止时间，否则写入会被忽略
+            let ticks = duration_to_tsc(duration).ok_or(TimerError::NotCalibrated)?;
+            local.set_tsc_deadline(read_tsc() + ticks.max(1));
+        }
+        Ok(())
+    })
+}
+
+/// Stops the local APIC timer.
+pub fn stop_timer() {
+    without_interrupts(|| {
+        if let Some(local) = apic::LOCAL_APIC.lock().as_mut() {
+            local.stop_timer();
+        }
+    });
+}
@@ -0,0 +1,280 @@
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+use crate::interrupts::{self, InterruptIndex, IrqError, TrapFrame};
+
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_SECONDS_ALARM: u8 = 0x01;
+const REG_MINUTES: u8 = 0x02;
+const REG_MINUTES_ALARM: u8 = 0x03;
+const REG_HOURS: u8 = 0x04;
+const REG_HOURS_ALARM: u8 = 0x05;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0a;
+const REG_STATUS_B: u8 = 0x0b;
+const REG_STATUS_C: u8 = 0x0c;
+/// The century register that the ACPI tables of QEMU and most PCs name.
+const REG_CENTURY: u8 = 0x32;
+
+/// Status A: an update of the time registers is in progress.
+const UPDATE_IN_PROGRESS: u8 = 1 << 7;
+const RATE_MASK: u8 = 0x0f;
+/// Status B: the hours register counts from 0 to 23.
+const HOURS_24: u8 = 1 << 1;
+/// Status B: the time registers are binary instead of BCD.
+const BINARY: u8 = 1 << 2;
+const ALARM_INTERRUPT: u8 = 1 << 5;
+const PERIODIC_INTERRUPT: u8 = 1 << 6;
+/// Status C: the flags of the pending interrupts.
+const ALARM_FLAG: u8 = 1 << 5;
+const PERIODIC_FLAG: u8 = 1 << 6;
+/// The PM flag of the hours register in 12-hour mode.
+const HOURS_PM: u8 = 1 << 7;
+
+/// The frequency of the oscillator the periodic interrupt is derived from.
+const BASE_FREQUENCY: u32 = 32768;
+
+/// A calendar date and time of day, in the time zone the RTC is set to,
+/// which is UTC on QEMU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Decodes the time registers `[second, minute, hour, day, month, year,
+    /// century]` with the format given by status register B.
+    ///
+    /// A century of 0 means the RTC has no century register, then the years
+    /// 00 to 99 are taken as 2000 to 2099.
+    pub fn from_registers(registers: [u8; 7], status_b: u8) -> Self {
+        let [second, minute, hour, day, month, year, century] = registers;
+        let decode = |value: u8| if status_b & BINARY != 0 { value } else { bcd_to_binary(value) };
+        // 12小时制的PM标志位在BCD和二进制格式中都是最高位，12点表示0点或12点
+        let mut hour24 = decode(hour & !HOURS_PM);
+        if status_b & HOURS_24 == 0 {
+            hour24 %= 12;
+            if hour & HOURS_PM != 0 {
+                hour24 += 12;
+            }
+        }
+        let century = if century == 0 { 20 } else { u16::from(decode(century)) };
+        DateTime {
+            year: century * 100 + u16::from(decode(year)),
+            month: decode(month),
+            day: decode(day),
+            hour: hour24,
+            minute: decode(minute),
+            second: decode(second),
+        }
+    }
+
+    /// Returns the seconds since 1970-01-01 00:00:00.
+    pub fn unix_timestamp(&self) -> u64 {
+        // 公历日期转换为天数，每400年为一个周期，3月作为一年的第一个月
+        let year = i64::from(self.year) - if self.month <= 2 { 1 } else { 0 };
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let month = i64::from(self.month);
+        let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + i64::from(self.day) - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146_097 + day_of_era - 719_468;
+        let seconds = i64::from(self.hour) * 3600 + i64::from(self.minute) * 60 + i64::from(self.second);
+        (days * 86400 + seconds) as u64
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+fn bcd_to_binary(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0f)
+}
+
+fn binary_to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+}
+
+/// An error configuring the RTC interrupts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RtcError {
+    /// The periodic interrupt frequency is not a power of two from 2 to 8192.
+    InvalidFrequency,
+    /// The alarm time is not a valid time of day.
+    InvalidTime,
+}
+
+impl fmt::Display for RtcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RtcError::InvalidFrequency => write!(f, "rtc frequency must be a power of two from 2 to 8192"),
+            RtcError::InvalidTime => write!(f, "invalid time of day for the rtc alarm"),
+        }
+    }
+}
+
+struct Cmos {
+    address: Port<u8>,
+    data: Port<u8>,
+}
+
+impl Cmos {
+    fn read(&mut self, register: u8) -> u8 {
+        unsafe {
+            self.address.write(register);
+            self.data.read()
+        }
+    }
+
+    fn write(&mut self, register: u8, value: u8) {
+        unsafe {
+            self.address.write(register);
+            self.data.write(value);
+        }
+    }
+
+    fn update(&mut self, register: u8, f: impl FnOnce(u8) -> u8) {
+        let value = self.read(register);
+        self.write(register, f(value));
+    }
+
+    fn read_time_registers(&mut self) -> [u8; 7] {
+        while self.read(REG_STATUS_A) & UPDATE_IN_PROGRESS != 0 {
+            core::hint::spin_loop();
+        }
+        let mut registers = [REG_SECONDS, REG_MINUTES, REG_HOURS, REG_DAY, REG_MONTH, REG_YEAR, REG_CENTURY];
+        for register in registers.iter_mut() {
+            *register = self.read(*register);
+        }
+        registers
+    }
+}
+
+// CMOS的索引端口和数据端口必须成对访问，中断处理函数也会访问，必须在关中断时加锁
+static CMOS: Mutex<Cmos> = Mutex::new(Cmos {
+    address: Port::new(CMOS_ADDRESS),
+    data: Port::new(CMOS_DATA),
+});
+
+static PERIODIC_INTERRUPTS: AtomicU64 = AtomicU64::new(0);
+static ALARMS: AtomicU64 = AtomicU64::new(0);
+
+/// Reads the current date and time from the RTC.
+pub fn now() -> DateTime {
+    without_interrupts(|| {
+        let mut cmos = CMOS.lock();
+        // 读取过程中可能开始一次更新，连续两次读到相同的值才是一致的
+        let mut registers = cmos.read_time_registers();
+        loop {
+            let again = cmos.read_time_registers();
+            if again == registers {
+                break;
+            }
+            registers = again;
+        }
+        DateTime::from_registers(registers, cmos.read(REG_STATUS_B))
+    })
+}
+
+/// Registers the handler of the RTC interrupt on IRQ 8. The periodic and the
+/// alarm interrupt stay disabled until `set_periodic_frequency` and
+/// `set_alarm`.
+pub fn init() -> Result<(), IrqError> {
+    without_interrupts(|| {
+        let mut cmos = CMOS.lock();
+        cmos.update(REG_STATUS_B, |b| b & !(PERIODIC_INTERRUPT | ALARM_INTERRUPT));
+        // 读取状态寄存器C清除挂起的中断，否则RTC不会再产生中断
+        cmos.read(REG_STATUS_C);
+    });
+    interrupts::register_irq(InterruptIndex::Rtc.as_irq(), rtc_interrupt_handler)
+}
+
+fn rtc_interrupt_handler(_frame: &mut TrapFrame) {
+    let flags = CMOS.lock().read(REG_STATUS_C);
+    if flags & PERIODIC_FLAG != 0 {
+        PERIODIC_INTERRUPTS.fetch_add(1, Ordering::Relaxed);
+    }
+    if flags & ALARM_FLAG != 0 {
+        ALARMS.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// Enables the periodic interrupt with `hz` interrupts per second, which must
+/// be a power of two from 2 to 8192.
+pub fn set_periodic_frequency(hz: u32) -> Result<(), RtcError> {
+    if !hz.is_power_of_two() || !(2..=8192).contains(&hz) {
+        return Err(RtcError::InvalidFrequency);
+    }
+    // 频率 = 32768 >> (rate - 1)
+    let rate = (BASE_FREQUENCY / hz).trailing_zeros() as u8 + 1;
+    without_interrupts(|| {
+        let mut cmos = CMOS.lock();
+        cmos.update(REG_STATUS_A, |a| (a & !RATE_MASK) | rate);
+        cmos.update(REG_STATUS_B, |b| b | PERIODIC_INTERRUPT);
+    });
+    Ok(())
+}
+
+/// Disables the periodic interrupt.
+pub fn disable_periodic() {
+    without_interrupts(|| CMOS.lock().update(REG_STATUS_B, |b| b & !PERIODIC_INTERRUPT));
+}
+
+/// Returns the number of periodic interrupts since boot.
+pub fn periodic_interrupts() -> u64 {
+    PERIODIC_INTERRUPTS.load(Ordering::Relaxed)
+}
+
+/// Enables the alarm interrupt, which fires every day at the given time of
+/// day in the time zone of the RTC.
+pub fn set_alarm(hour: u8, minute: u8, second: u8) -> Result<(), RtcError> {
+    if hour > 23 || minute > 59 || second > 59 {
+        return Err(RtcError::InvalidTime);
+    }
+    without_interrupts(|| {
+        let mut cmos = CMOS.lock();
+        let status_b = cmos.read(REG_STATUS_B);
+        let encode = |value: u8| if status_b & BINARY != 0 { value } else { binary_to_bcd(value) };
+        let hour = if status_b & HOURS_24 != 0 {
+            encode(hour)
+        } else if hour >= 12 {
+            encode(if hour == 12 { 12 } else { hour - 12 }) | HOURS_PM
+        } else {
+            encode(if hour == 0 { 12 } else { hour })
+        };
+        cmos.write(REG_SECONDS_ALARM, encode(second));
+        cmos.write(REG_MINUTES_ALARM, encode(minute));
+        cmos.write(REG_HOURS_ALARM, hour);
+        cmos.write(REG_STATUS_B, status_b | ALARM_INTERRUPT);
+    });
+    Ok(())
+}
+
+/// Disables the alarm interrupt.
+pub fn disable_alarm() {
+    without_interrupts(|| CMOS.lock().update(REG_STATUS_B, |b| b & !ALARM_INTERRUPT));
+}
+
+/// Returns the number of alarm interrupts since boot.
+pub fn alarms() -> u64 {
+    ALARMS.load(Ordering::SeqCst)
+}
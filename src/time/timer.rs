@@ -0,0 +1,231 @@
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BinaryHeap};
+use core::cmp::Reverse;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::time::Duration;
+use lazy_static::lazy_static;
+use spin::Mutex;
+use super::uptime;
+
+/// The heap is rebuilt when it holds more than twice the pending timers plus
+/// this many entries.
+const COMPACT_SLACK: usize = 16;
+
+/// Identifies a timer created with `add_timer` or `add_periodic`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimerId(u64);
+
+enum Callback {
+    Once(Box<dyn FnOnce() + Send>),
+    Periodic(Box<dyn FnMut() + Send>, u64),
+}
+
+struct Timer {
+    deadline: u64,
+    // 回调正在执行时为None，执行完后放回
+    callback: Option<Callback>,
+}
+
+/// The pending timers ordered by deadline in a min-heap. Cancelled timers
+/// are only removed from `timers`, their heap entries are dropped when they
+/// reach the top or when too many of them pile up, see `compact`.
+struct TimerQueue {
+    next_id: u64,
+    queue: BinaryHeap<Reverse<(u64, u64)>>,
+    timers: BTreeMap<u64, Timer>,
+}
+
+impl TimerQueue {
+    fn insert(&mut self, id: u64, deadline: u64, callback: Callback) {
+        self.queue.push(Reverse((deadline, id)));
+        self.timers.insert(id, Timer { deadline, callback: Some(callback) });
+        self.update_next_deadline();
+    }
+
+    /// Removes the earliest timer that expired at `now` from the queue and
+    /// takes its callback. Periodic timers stay registered until `reschedule`.
+    fn pop_expired(&mut self, now: u64) -> Option<(u64, u64, Callback)> {
+        let mut expired = None;
+        while let Some(&Reverse((deadline, id))) = self.queue.peek() {
+            if deadline > now {
+                break;
+            }
+            self.queue.pop();
+            let timer = match self.timers.get_mut(&id) {
+                Some(timer) if timer.deadline == deadline => timer,
+                // 已经取消的定时器
+                _ => continue,
+            };
+            let callback = timer.callback.take().expect("expired timer without callback");
+            if let Callback::Once(_) = callback {
+                self.timers.remove(&id);
+            }
+            expired = Some((id, deadline, callback));
+            break;
+        }
+        self.update_next_deadline();
+        expired
+    }
+
+    /// Puts the callback of a periodic timer back after it ran, unless the
+    /// timer was cancelled meanwhile.
+    fn reschedule(&mut self, id: u64, deadline: u64, callback: Callback) {
+        if let Some(timer) = self.timers.get_mut(&id) {
+            timer.deadline = deadline;
+            timer.callback = Some(callback);
+            self.queue.push(Reverse((deadline, id)));
+            self.update_next_deadline();
+        }
+    }
+
+    /// Removes a timer and returns whether it was pending.
+    fn remove(&mut self, id: u64) -> bool {
+        if self.timers.remove(&id).is_none() {
+            return false;
+        }
+        self.compact();
+        self.update_next_deadline();
+        true
+    }
+
+    /// Rebuilds the heap from the pending timers once it consists mostly of
+    /// cancelled entries, so that cancelling and adding timers in a loop does
+    /// not grow it without bound.
+    fn compact(&mut self) {
+        if self.queue.len() <= 2 * self.timers.len() + COMPACT_SLACK {
+            return;
+        }
+        // 正在执行回调的周期定时器不在堆中，由`reschedule`放回
+        self.queue = self
+            .timers
+            .iter()
+            .filter(|(_, timer)| timer.callback.is_some())
+            .map(|(&id, timer)| Reverse((timer.deadline, id)))
+            .collect();
+    }
+
+    fn update_next_deadline(&mut self) {
+        // 丢弃堆顶已经取消的定时器，否则它们的截止时间会引起多余的唤醒
+        while let Some(&Reverse((deadline, id))) = self.queue.peek() {
+            match self.timers.get(&id) {
+                Some(timer) if timer.deadline == deadline => break,
+                _ => {
+                    self.queue.pop();
+                }
+            }
+        }
+        let next = self.queue.peek().map_or(u64::MAX, |&Reverse((deadline, _))| deadline);
+        NEXT_DEADLINE.store(next, Ordering::SeqCst);
+    }
+}
+
+lazy_static! {
+    // 队列只在线程上下文中访问，时钟中断只读取NEXT_DEADLINE
+    static ref TIMERS: Mutex<TimerQueue> = Mutex::new(TimerQueue {
+        next_id: 0,
+        queue: BinaryHeap::new(),
+        timers: BTreeMap::new(),
+    });
+}
+
+// 时钟中断只比较最早的截止时间并设置标志，不访问队列
+static NEXT_DEADLINE: AtomicU64 = AtomicU64::new(u64::MAX);
+static EXPIRED: AtomicBool = AtomicBool::new(false);
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+fn uptime_nanos() -> u64 {
+    uptime().as_nanos() as u64
+}
+
+fn add(delay: Duration, callback: Callback) -> TimerId {
+    let deadline = uptime_nanos().saturating_add(delay.as_nanos() as u64);
+    let mut timers = TIMERS.lock();
+    let id = timers.next_id;
+    timers.next_id += 1;
+    timers.insert(id, deadline, callback);
+    TimerId(id)
+}
+
+/// Calls `callback` once after at least `delay`.
+///
+/// Timers are checked on every timer interrupt, so they fire up to one
+/// timer tick late. Timers with the same deadline fire in the order they
+/// were added. The callback does not run in the interrupt handler but in
+/// `run_expired`.
+pub fn add_timer(delay: Duration, callback: impl FnOnce() + Send + 'static) -> TimerId {
+    add(delay, Callback::Once(Box::new(callback)))
+}
+
+/// Calls `callback` every `period` until the timer is cancelled. If the
+/// callbacks fall behind by more than a period, the missed calls are skipped.
+pub fn add_periodic(period: Duration, callback: impl FnMut() + Send + 'static) -> TimerId {
+    assert!(period.as_nanos() > 0, "the period of a timer must not be zero");
+    add(period, Callback::Periodic(Box::new(callback), period.as_nanos() as u64))
+}
+
+/// Cancels a timer and returns whether it was still pending. A callback that
+/// is already running completes, but a periodic timer is not called again.
+pub fn cancel(id: TimerId) -> bool {
+    TIMERS.lock().remove(id.0)
+}
+
+/// Returns the number of pending timers.
+pub fn pending() -> usize {
+    TIMERS.lock().timers.len()
+}
+
+/// Returns the uptime at which the earliest pending timer expires.
+pub fn next_deadline() -> Option<Duration> {
+    match NEXT_DEADLINE.load(Ordering::SeqCst) {
+        u64::MAX => None,
+        nanos => Some(Duration::from_nanos(nanos)),
+    }
+}
+
+// 单独的函数保证锁在执行回调之前释放
+fn pop_expired(now: u64) -> Option<(u64, u64, Callback)> {
+    TIMERS.lock().pop_expired(now)
+}
+
+/// Checks for expired timers, called by the timer interrupt handler.
+pub(crate) fn on_tick() {
+    if uptime_nanos() >= NEXT_DEADLINE.load(Ordering::SeqCst) {
+        EXPIRED.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Runs the callbacks of all expired timers in deadline order and returns
+/// how many ran.
+///
+/// The idle loop and `time::sleep` call it after every interrupt, so
+/// callbacks run with interrupts enabled, outside of any interrupt handler.
+/// Calls from within a callback return 0.
+pub fn run_expired() -> usize {
+    if !EXPIRED.swap(false, Ordering::SeqCst) {
+        return 0;
+    }
+    if RUNNING.swap(true, Ordering::SeqCst) {
+        EXPIRED.store(true, Ordering::SeqCst);
+        return 0;
+    }
+    let now = uptime_nanos();
+    let mut count = 0;
+    // 每次只取出一个回调，回调执行时不持有锁，可以添加或取消定时器
+    while let Some((id, deadline, callback)) = pop_expired(now) {
+        match callback {
+            Callback::Once(callback) => callback(),
+            Callback::Periodic(mut callback, period) => {
+                callback();
+                let mut next = deadline + period;
+                if next <= now {
+                    next = now + period;
+                }
+                let callback = Callback::Periodic(callback, period);
+                TIMERS.lock().reschedule(id, next, callback);
+            }
+        }
+        count += 1;
+    }
+    RUNNING.store(false, Ordering::SeqCst);
+    count
+}
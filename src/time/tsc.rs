@@ -0,0 +1,134 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+use crate::interrupts::apic;
+use super::{uptime, PIT, PIT_COMMAND, PIT_FREQUENCY};
+
+/// The length of the PIT window `calibrate` measures against.
+pub const CALIBRATION_WINDOW: Duration = Duration::from_millis(20);
+
+const PIT_CHANNEL_2: u16 = 0x42;
+const PIT_GATE: u16 = 0x61;
+/// Gate input of channel 2 in port 0x61.
+const GATE_ENABLE: u8 = 1 << 0;
+/// Connects the output of channel 2 to the PC speaker.
+const SPEAKER_ENABLE: u8 = 1 << 1;
+/// The output of channel 2, read back through port 0x61.
+const GATE_OUTPUT: u8 = 1 << 5;
+/// Channel 2, lobyte/hibyte access, mode 0 (interrupt on terminal count), binary.
+const PIT_ONE_SHOT: u8 = 0b1011_0000;
+
+/// The frequencies measured by `calibrate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Calibration {
+    /// The frequency of the time stamp counter in Hz.
+    pub tsc_hz: u64,
+    /// The frequency at which the local APIC timer counts down with the bus
+    /// clock divided by 16, or `None` if the local APIC is not enabled.
+    pub apic_timer_hz: Option<u64>,
+}
+
+// 单调时钟 = OFFSET_NANOS + (TSC - TSC_BASE) / TSC_HZ，校准之前使用时钟中断的计数
+static TSC_HZ: AtomicU64 = AtomicU64::new(0);
+static TSC_BASE: AtomicU64 = AtomicU64::new(0);
+static OFFSET_NANOS: AtomicU64 = AtomicU64::new(0);
+static APIC_TIMER_HZ: AtomicU64 = AtomicU64::new(0);
+
+/// Reads the time stamp counter.
+pub fn read_tsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// Measures the frequencies of the TSC and, if the local APIC is enabled, of
+/// the local APIC timer during `CALIBRATION_WINDOW` counted down by channel 2
+/// of the PIT, and switches `monotonic` to the TSC.
+///
+/// Interrupts are disabled while measuring. Call it after
+/// `interrupts::enable_apic` to calibrate the local APIC timer as well.
+pub fn calibrate() -> Calibration {
+    let latch = (u64::from(PIT_FREQUENCY) * CALIBRATION_WINDOW.as_nanos() as u64 / 1_000_000_000) as u16;
+    let window_nanos = u128::from(latch) * 1_000_000_000 / u128::from(PIT_FREQUENCY);
+
+    let calibration = without_interrupts(|| {
+        let _pit = PIT.lock();
+        let mut local_apic = apic::LOCAL_APIC.lock();
+        let mut gate = Port::<u8>::new(PIT_GATE);
+        let mut command = Port::<u8>::new(PIT_COMMAND);
+        let mut data = Port::<u8>::new(PIT_CHANNEL_2);
+        let saved_gate = unsafe { gate.read() };
+        let (tsc, count) = unsafe {
+            // 打开通道2的门控但不连接扬声器，写入计数值后通道2开始倒数
+            gate.write((saved_gate & !SPEAKER_ENABLE) | GATE_ENABLE);
+            command.write(PIT_ONE_SHOT);
+            data.write(latch as u8);
+            data.write((latch >> 8) as u8);
+            if let Some(local) = local_apic.as_mut() {
+                local.start_timer(apic::TimerMode::OneShot, u32::MAX, true);
+            }
+            let start = read_tsc();
+            while gate.read() & GATE_OUTPUT == 0 {}
+            let tsc = read_tsc() - start;
+            let count = local_apic.as_mut().map(|local| {
+                let count = local.timer_count();
+                local.stop_timer();
+                u32::MAX - count
+            });
+            gate.write(saved_gate);
+            (tsc, count)
+        };
+        Calibration {
+            tsc_hz: (u128::from(tsc) * 1_000_000_000 / window_nanos) as u64,
+            apic_timer_hz: count.map(|count| (u128::from(count) * 1_000_000_000 / window_nanos) as u64),
+        }
+    });
+
+    without_interrupts(|| {
+        // 从当前时间继续计时，保证切换到TSC时单调时钟不会倒退
+        let now = nanos();
+        TSC_HZ.store(0, Ordering::SeqCst);
+        OFFSET_NANOS.store(now, Ordering::SeqCst);
+        TSC_BASE.store(read_tsc(), Ordering::SeqCst);
+        APIC_TIMER_HZ.store(calibration.apic_timer_hz.unwrap_or(0), Ordering::SeqCst);
+        TSC_HZ.store(calibration.tsc_hz, Ordering::SeqCst);
+    });
+    calibration
+}
+
+/// Returns the result of the last `calibrate`.
+pub fn calibration() -> Option<Calibration> {
+    let tsc_hz = TSC_HZ.load(Ordering::SeqCst);
+    if tsc_hz == 0 {
+        return None;
+    }
+    let apic_timer_hz = APIC_TIMER_HZ.load(Ordering::SeqCst);
+    Some(Calibration { tsc_hz, apic_timer_hz: if apic_timer_hz == 0 { None } else { Some(apic_timer_hz) } })
+}
+
+/// Returns the nanoseconds since the timer interrupt was enabled.
+///
+/// After `calibrate` the value comes from the TSC, before it has the
+/// resolution of one timer tick like `uptime`.
+pub fn nanos() -> u64 {
+    let tsc_hz = TSC_HZ.load(Ordering::SeqCst);
+    if tsc_hz == 0 {
+        return uptime().as_nanos() as u64;
+    }
+    let elapsed = read_tsc().saturating_sub(TSC_BASE.load(Ordering::SeqCst));
+    OFFSET_NANOS.load(Ordering::SeqCst) + (u128::from(elapsed) * 1_000_000_000 / u128::from(tsc_hz)) as u64
+}
+
+/// Returns the time since the timer interrupt was enabled, see `nanos`. The
+/// value never decreases.
+pub fn monotonic() -> Duration {
+    Duration::from_nanos(nanos())
+}
+
+/// Converts a duration into TSC ticks, or `None` before `calibrate`.
+pub fn duration_to_tsc(duration: Duration) -> Option<u64> {
+    let tsc_hz = TSC_HZ.load(Ordering::SeqCst);
+    if tsc_hz == 0 {
+        return None;
+    }
+    Some((duration.as_nanos() * u128::from(tsc_hz) / 1_000_000_000) as u64)
+}
@@ -0,0 +1,110 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os_626::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use os_626::{serial_print, serial_println};
+use os_626::memory::{
+    self,
+    address_space,
+    user::{self, USER_START},
+    vm::VmError,
+    AddressSpace, FRAME_ALLOCATOR,
+};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use x86_64::{
+    structures::paging::{Page, PageTableFlags},
+    VirtAddr,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    os_626::init();
+    os_626::test_init_memory(boot_info);
+
+    test_main();
+    os_626::hlt_loop();
+}
+
+fn used_frames() -> usize {
+    FRAME_ALLOCATOR.lock().as_ref().unwrap().used_frames()
+}
+
+fn user_page(index: u64) -> Page {
+    Page::containing_address(VirtAddr::new(USER_START + index * 4096))
+}
+
+const FLAGS: PageTableFlags = PageTableFlags::WRITABLE;
+
+#[test_case]
+fn user_pages_are_private() {
+    serial_print!("user_pages_are_private... ");
+    let mut first = AddressSpace::new().expect("creating the address space failed");
+    let mut second = AddressSpace::new().expect("creating the address space failed");
+    first.map_user_page(user_page(0), FLAGS).unwrap();
+    second.map_user_page(user_page(0), FLAGS).unwrap();
+    let addr = user_page(0).start_address();
+
+    first.activate();
+    user::copy_to_user(addr, b"first").unwrap();
+    second.activate();
+    user::copy_to_user(addr, b"other").unwrap();
+    first.activate();
+    let mut buf = [0u8; 5];
+    user::copy_from_user(&mut buf, addr).unwrap();
+    assert_eq!(&buf, b"first");
+    address_space::switch_to_kernel();
+
+    // 内核地址空间中看不到用户页
+    assert!(unsafe { memory::translate_addr(addr, memory::physical_memory_offset()) }.is_none());
+    assert_ne!(first.translate(addr).map(|t| t.0), second.translate(addr).map(|t| t.0));
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn kernel_stays_mapped() {
+    serial_print!("kernel_stays_mapped... ");
+    static VALUE: u64 = 0x_1234_5678;
+    let space = AddressSpace::new().unwrap();
+    space.activate();
+    assert!(space.is_active());
+    // 代码、栈和静态变量在新的地址空间中仍然可以访问
+    let local = 7u64;
+    assert_eq!(unsafe { core::ptr::read_volatile(&VALUE) } + local, 0x_1234_567f);
+    drop(space);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn drop_frees_all_frames() {
+    serial_print!("drop_frees_all_frames... ");
+    let used = used_frames();
+    let mut space = AddressSpace::new().unwrap();
+    for index in 0..4 {
+        space.map_user_page(user_page(index * 512), FLAGS).unwrap();
+    }
+    space.unmap_user_page(user_page(0)).unwrap();
+    assert!(space.translate(user_page(0).start_address()).is_none());
+    assert!(used_frames() > used);
+    drop(space);
+    assert_eq!(used_frames(), used);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn kernel_pages_are_rejected() {
+    serial_print!("kernel_pages_are_rejected... ");
+    let mut space = AddressSpace::new().unwrap();
+    let kernel_page = Page::containing_address(VirtAddr::new(0x_6000_0000_0000));
+    assert_eq!(space.map_user_page(kernel_page, FLAGS).unwrap_err(), VmError::InvalidRange);
+    assert_eq!(space.unmap_user_page(user_page(1)).unwrap_err(), VmError::NotFound);
+    serial_println!("[ok]");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os_626::test_panic_handler(info)
+}
@@ -0,0 +1,91 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![feature(asm)]
+#![test_runner(os_626::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use os_626::{serial_print, serial_println};
+use os_626::interrupts::{self, apic, ApicError, TrapFrame};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::instructions::port::Port;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    os_626::init();
+    os_626::test_init_memory(boot_info);
+
+    test_main();
+    os_626::hlt_loop();
+}
+
+#[test_case]
+fn enable() {
+    serial_print!("enable... ");
+    if cfg!(feature = "legacy-pic") {
+        assert_eq!(interrupts::enable_apic(), Err(ApicError::Disabled));
+        assert!(!interrupts::apic_enabled());
+    } else {
+        assert_eq!(interrupts::enable_apic(), Ok(()));
+        assert!(interrupts::apic_enabled());
+        // 8259的所有线都被屏蔽
+        let masks: (u8, u8) = unsafe { (Port::new(0x21).read(), Port::new(0xa1).read()) };
+        assert_eq!(masks, (0xff, 0xff));
+        assert!(!interrupts::is_masked(0));
+        assert!(interrupts::is_masked(5));
+    }
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn timer_through_io_apic() {
+    serial_print!("timer_through_io_apic... ");
+    // 如果没有EOI，本地APIC不会再传递同一优先级的中断
+    for _ in 0..3 {
+        let ticks = interrupts::irq_count(0);
+        while interrupts::irq_count(0) == ticks {
+            x86_64::instructions::hlt();
+        }
+    }
+    serial_println!("[ok]");
+}
+
+static CALLS: AtomicU64 = AtomicU64::new(0);
+
+fn handler(_frame: &mut TrapFrame) {
+    CALLS.fetch_add(1, Ordering::SeqCst);
+}
+
+#[test_case]
+fn register_unmasks_pin() {
+    serial_print!("register_unmasks_pin... ");
+    interrupts::register_irq(5, handler).unwrap();
+    assert!(!interrupts::is_masked(5));
+    unsafe { asm!("int 37") };
+    assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+    interrupts::unregister_irq(5).unwrap();
+    assert!(interrupts::is_masked(5));
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn io_apic_pins() {
+    serial_print!("io_apic_pins... ");
+    assert_eq!(apic::io_apic_pin(0), 2);
+    assert_eq!(apic::io_apic_pin(1), 1);
+    if interrupts::apic_enabled() {
+        let pins = x86_64::instructions::interrupts::without_interrupts(|| {
+            apic::IO_APIC.lock().as_ref().map(|io| io.pins())
+        });
+        assert!(pins.unwrap() >= 16);
+    }
+    serial_println!("[ok]");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os_626::test_panic_handler(info)
+}
@@ -0,0 +1,83 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os_626::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use os_626::{backtrace, memory, serial_print, serial_println};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    os_626::init();
+    // 遍历栈帧时需要通过物理内存映射检查页表
+    unsafe { memory::init(VirtAddr::new(boot_info.physical_memory_offset)) };
+
+    test_main();
+    os_626::hlt_loop();
+}
+
+#[test_case]
+fn symbol_table_is_embedded() {
+    serial_print!("symbol_table_is_embedded... ");
+    // 通过`ksyms --run`运行时必须有符号表，见README
+    if backtrace::symbols_expected() {
+        assert!(backtrace::has_symbols(), "run through `ksyms --run`, but the symbol table is empty");
+    }
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn symbolize_function() {
+    serial_print!("symbolize_function... ");
+    if !backtrace::has_symbols() {
+        assert_eq!(backtrace::symbolize(VirtAddr::new(outer as usize as u64)), None);
+        serial_println!("[ok]");
+        return;
+    }
+    let addr = VirtAddr::new(outer as usize as u64);
+    assert_eq!(backtrace::symbolize(addr), Some(("backtrace::outer", 0)));
+    let (name, offset) = backtrace::symbolize(addr + 4u64).unwrap();
+    assert_eq!((name, offset), ("backtrace::outer", 4));
+    assert_eq!(backtrace::symbolize(VirtAddr::new(0x1000)), None);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn walk_frames() {
+    serial_print!("walk_frames... ");
+    let mut addrs = [VirtAddr::zero(); 2];
+    outer(&mut addrs);
+    // 第一个返回地址位于调用`inner`的函数中
+    let outer_start = outer as usize as u64;
+    assert!(addrs[0].as_u64() > outer_start && addrs[0].as_u64() < outer_start + 0x100, "{:?}", addrs);
+    if backtrace::has_symbols() {
+        let name = |addr: VirtAddr| backtrace::symbolize(addr - 1u64).map_or("?", |(name, _)| name);
+        assert_eq!([name(addrs[0]), name(addrs[1])], ["backtrace::outer", "backtrace::walk_frames"]);
+    }
+    serial_println!("[ok]");
+}
+
+#[inline(never)]
+fn outer(addrs: &mut [VirtAddr; 2]) {
+    inner(addrs);
+}
+
+#[inline(never)]
+fn inner(addrs: &mut [VirtAddr; 2]) {
+    let mut index = 0;
+    backtrace::walk(backtrace::frame_pointer(), |addr| {
+        if index < addrs.len() {
+            addrs[index] = addr;
+            index += 1;
+        }
+    });
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os_626::test_panic_handler(info)
+}
@@ -0,0 +1,114 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os_626::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use os_626::{serial_print, serial_println};
+use os_626::memory::BuddyFrameAllocator;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use spin::Mutex;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size2MiB,
+};
+
+static FRAME_ALLOCATOR: Mutex<Option<BuddyFrameAllocator>> = Mutex::new(None);
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use x86_64::VirtAddr;
+
+    os_626::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let frame_allocator = unsafe {
+        BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    *FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+
+    test_main();
+    os_626::hlt_loop();
+}
+
+fn with_allocator<F: FnOnce(&mut BuddyFrameAllocator)>(f: F) {
+    f(FRAME_ALLOCATOR.lock().as_mut().expect("frame allocator not initialized"))
+}
+
+#[test_case]
+fn single_frame_merges_back() {
+    serial_print!("single_frame_merges_back... ");
+    with_allocator(|allocator| {
+        let before = allocator.stats();
+        let frame: PhysFrame = allocator.allocate_frame().expect("allocation failed");
+        assert_eq!(allocator.stats().free_frames, before.free_frames - 1);
+        unsafe { allocator.deallocate_frame(frame) };
+        assert_eq!(allocator.stats(), before);
+    });
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn contiguous_blocks_are_aligned() {
+    serial_print!("contiguous_blocks_are_aligned... ");
+    with_allocator(|allocator| {
+        let before = allocator.stats();
+        let mut blocks: [Option<PhysFrame>; 8] = [None; 8];
+        for (order, slot) in blocks.iter_mut().enumerate() {
+            let frame = allocator.allocate(order).expect("allocation failed");
+            assert_eq!(frame.start_address().as_u64() % (4096 << order), 0);
+            *slot = Some(frame);
+        }
+        for (order, slot) in blocks.iter_mut().enumerate() {
+            unsafe { allocator.deallocate(slot.take().unwrap(), order) };
+        }
+        assert_eq!(allocator.stats(), before);
+    });
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn contiguous_rounds_up() {
+    serial_print!("contiguous_rounds_up... ");
+    with_allocator(|allocator| {
+        let before = allocator.stats();
+        let frame = allocator.allocate_contiguous(5).expect("allocation failed");
+        assert_eq!(allocator.stats().free_frames, before.free_frames - 8);
+        unsafe { allocator.deallocate_contiguous(frame, 5) };
+        assert_eq!(allocator.stats(), before);
+    });
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn huge_frame_allocation() {
+    serial_print!("huge_frame_allocation... ");
+    with_allocator(|allocator| {
+        let before = allocator.stats();
+        let frame: PhysFrame<Size2MiB> = allocator.allocate_frame().expect("allocation failed");
+        assert_eq!(frame.start_address().as_u64() % Size2MiB::SIZE, 0);
+        assert_eq!(allocator.stats().free_frames, before.free_frames - 512);
+        unsafe { allocator.deallocate_frame(frame) };
+        assert_eq!(allocator.stats(), before);
+    });
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn fragmentation_statistics() {
+    serial_print!("fragmentation_statistics... ");
+    with_allocator(|allocator| {
+        let stats = allocator.stats();
+        assert!(stats.free_frames <= stats.usable_frames);
+        let order = stats.largest_free_order().expect("no free memory");
+        assert_eq!(stats.fragmentation(0), 0);
+        assert!(stats.fragmentation(order) < 100);
+        assert_eq!(stats.fragmentation(order + 1), 100);
+    });
+    serial_println!("[ok]");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os_626::test_panic_handler(info)
+}
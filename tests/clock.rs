@@ -0,0 +1,152 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os_626::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use os_626::{serial_print, serial_println, time};
+use os_626::interrupts::{self, apic, TrapFrame};
+use os_626::time::{TimerError, TimerMode};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    os_626::init();
+    os_626::test_init_memory(boot_info);
+    let _ = interrupts::enable_apic();
+    time::calibrate();
+    apic::set_timer_handler(Some(handler));
+
+    test_main();
+    os_626::hlt_loop();
+}
+
+static FIRED: AtomicU64 = AtomicU64::new(0);
+
+fn handler(_frame: &mut TrapFrame) {
+    FIRED.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Halts until the local APIC timer fired `count` times more than `start`,
+/// or panics after one second.
+fn wait_fired(start: u64, count: u64) {
+    let deadline = time::uptime() + Duration::from_secs(1);
+    while FIRED.load(Ordering::SeqCst) < start + count {
+        let fired = FIRED.load(Ordering::SeqCst) - start;
+        assert!(time::uptime() < deadline, "the local apic timer fired {} times", fired);
+        x86_64::instructions::hlt();
+    }
+}
+
+#[test_case]
+fn calibrated() {
+    serial_print!("calibrated... ");
+    let calibration = time::calibration().unwrap();
+    // 任何能运行内核的CPU的TSC都不会低于10MHz
+    assert!(calibration.tsc_hz > 10_000_000, "{:?}", calibration);
+    assert_eq!(calibration.apic_timer_hz.is_some(), interrupts::apic_enabled());
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn monotonic_across_interrupts() {
+    serial_print!("monotonic_across_interrupts... ");
+    let ticks = time::ticks();
+    let mut last = time::nanos();
+    let mut changes = 0;
+    while time::ticks() < ticks + 10 {
+        let now = time::nanos();
+        assert!(now >= last, "{} after {}", now, last);
+        if now > last {
+            changes += 1;
+        }
+        last = now;
+    }
+    // 精度高于时钟中断的周期
+    assert!(changes > 10, "{} changes in 10 ticks", changes);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn agrees_with_sleep() {
+    serial_print!("agrees_with_sleep... ");
+    let start = time::monotonic();
+    time::sleep(Duration::from_millis(100));
+    let elapsed = time::monotonic() - start;
+    assert!(elapsed >= Duration::from_millis(90) && elapsed < Duration::from_millis(200), "{:?}", elapsed);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn one_shot() {
+    serial_print!("one_shot... ");
+    if !interrupts::apic_enabled() {
+        let result = time::start_timer(TimerMode::OneShot, Duration::from_millis(5));
+        assert_eq!(result, Err(TimerError::ApicDisabled));
+        serial_println!("[ok]");
+        return;
+    }
+    let fired = FIRED.load(Ordering::SeqCst);
+    time::start_timer(TimerMode::OneShot, Duration::from_millis(5)).unwrap();
+    wait_fired(fired, 1);
+    time::sleep(Duration::from_millis(30));
+    assert_eq!(FIRED.load(Ordering::SeqCst), fired + 1);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn periodic() {
+    serial_print!("periodic... ");
+    if !interrupts::apic_enabled() {
+        serial_println!("[ok]");
+        return;
+    }
+    let fired = FIRED.load(Ordering::SeqCst);
+    let start = time::monotonic();
+    time::start_timer(TimerMode::Periodic, Duration::from_millis(5)).unwrap();
+    wait_fired(fired, 5);
+    let elapsed = time::monotonic() - start;
+    time::stop_timer();
+    assert!(elapsed >= Duration::from_millis(20), "5 periods in {:?}", elapsed);
+    let stopped = FIRED.load(Ordering::SeqCst);
+    time::sleep(Duration::from_millis(30));
+    assert_eq!(FIRED.load(Ordering::SeqCst), stopped);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn tsc_deadline() {
+    serial_print!("tsc_deadline... ");
+    let (fired, start) = (FIRED.load(Ordering::SeqCst), time::monotonic());
+    let result = time::start_timer(TimerMode::TscDeadline, Duration::from_millis(5));
+    if !interrupts::apic_enabled() {
+        assert_eq!(result, Err(TimerError::ApicDisabled));
+    } else if !os_626::cpu::features().tsc_deadline {
+        assert_eq!(result, Err(TimerError::TscDeadlineUnsupported));
+    } else {
+        result.unwrap();
+        wait_fired(fired, 1);
+        assert!(time::monotonic() - start >= Duration::from_millis(4));
+        time::stop_timer();
+    }
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn out_of_range() {
+    serial_print!("out_of_range... ");
+    if interrupts::apic_enabled() {
+        let result = time::start_timer(TimerMode::OneShot, Duration::from_secs(1 << 40));
+        assert_eq!(result, Err(TimerError::OutOfRange));
+    }
+    serial_println!("[ok]");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os_626::test_panic_handler(info)
+}
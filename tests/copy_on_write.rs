@@ -0,0 +1,117 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os_626::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use os_626::{serial_print, serial_println};
+use os_626::memory::{
+    address_space,
+    cow::{self, COPY_ON_WRITE},
+    user::{self, USER_START},
+    AddressSpace, FRAME_ALLOCATOR,
+};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use x86_64::{
+    structures::paging::{Page, PageTableFlags, PhysFrame},
+    PhysAddr, VirtAddr,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    os_626::init();
+    os_626::test_init_memory(boot_info);
+
+    test_main();
+    os_626::hlt_loop();
+}
+
+fn used_frames() -> usize {
+    FRAME_ALLOCATOR.lock().as_ref().unwrap().used_frames()
+}
+
+const ADDR: u64 = USER_START;
+
+fn frame_of(space: &AddressSpace) -> PhysAddr {
+    space.translate(VirtAddr::new(ADDR)).expect("page not mapped").0
+}
+
+fn write(space: &AddressSpace, data: &[u8]) {
+    space.activate();
+    user::copy_to_user(VirtAddr::new(ADDR), data).expect("copy_to_user failed");
+    address_space::switch_to_kernel();
+}
+
+fn read(space: &AddressSpace) -> [u8; 6] {
+    let mut buf = [0; 6];
+    space.activate();
+    user::copy_from_user(&mut buf, VirtAddr::new(ADDR)).expect("copy_from_user failed");
+    address_space::switch_to_kernel();
+    buf
+}
+
+fn new_parent() -> AddressSpace {
+    let mut parent = AddressSpace::new().unwrap();
+    let page = Page::containing_address(VirtAddr::new(ADDR));
+    parent.map_user_page(page, PageTableFlags::WRITABLE).unwrap();
+    write(&parent, b"parent");
+    parent
+}
+
+#[test_case]
+fn clone_shares_frames_read_only() {
+    serial_print!("clone_shares_frames_read_only... ");
+    let mut parent = new_parent();
+    let child = parent.clone_cow().unwrap();
+    assert_eq!(frame_of(&parent), frame_of(&child));
+    for space in [&parent, &child].iter() {
+        let (_, flags) = space.translate(VirtAddr::new(ADDR)).unwrap();
+        assert!(!flags.contains(PageTableFlags::WRITABLE));
+        assert!(flags.contains(COPY_ON_WRITE));
+    }
+    assert_eq!(cow::ref_count(PhysFrame::containing_address(frame_of(&parent))), 2);
+    assert_eq!(&read(&child), b"parent");
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn writes_are_isolated() {
+    serial_print!("writes_are_isolated... ");
+    let mut parent = new_parent();
+    let child = parent.clone_cow().unwrap();
+    let shared = frame_of(&parent);
+
+    write(&child, b"child!");
+    assert_ne!(frame_of(&child), shared);
+    assert_eq!(&read(&child), b"child!");
+    assert_eq!(&read(&parent), b"parent");
+
+    // 子地址空间复制之后父地址空间是唯一的使用者，写入不再复制
+    write(&parent, b"again!");
+    assert_eq!(frame_of(&parent), shared);
+    assert_eq!(&read(&parent), b"again!");
+    assert_eq!(&read(&child), b"child!");
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn dropping_copies_frees_frames() {
+    serial_print!("dropping_copies_frees_frames... ");
+    let used = used_frames();
+    let mut parent = new_parent();
+    let child = parent.clone_cow().unwrap();
+    let shared = PhysFrame::containing_address(frame_of(&parent));
+    drop(parent);
+    assert_eq!(cow::ref_count(shared), 1);
+    assert_eq!(&read(&child), b"parent");
+    drop(child);
+    assert_eq!(used_frames(), used);
+    serial_println!("[ok]");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os_626::test_panic_handler(info)
+}
@@ -0,0 +1,30 @@
+#![no_std]
+#![no_main]
+#![feature(asm)]
+
+use core::panic::PanicInfo;
+use bootloader::{entry_point, BootInfo};
+use os_626::serial_print;
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    serial_print!("divide_error::divide_by_zero...\t");
+
+    os_626::init();
+
+    // Rust会在除数为0时直接panic，因此用`div`指令触发#DE
+    unsafe {
+        asm!("div {}", in(reg) 0u64, inout("rax") 1u64 => _, inout("rdx") 0u64 => _);
+    }
+
+    panic!("Execution continued after dividing by zero");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os_626::expect_crash_report(info, "divide_error::main", &[
+        "EXCEPTION: DIVIDE ERROR (#DE, vector 0)",
+        "RAX: 0x0000000000000001",
+    ])
+}
@@ -0,0 +1,107 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os_626::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use os_626::{serial_print, serial_println};
+use os_626::memory::BitmapFrameAllocator;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use spin::Mutex;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame};
+
+static FRAME_ALLOCATOR: Mutex<Option<BitmapFrameAllocator>> = Mutex::new(None);
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use x86_64::VirtAddr;
+
+    os_626::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let frame_allocator = unsafe {
+        BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    *FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+
+    test_main();
+    os_626::hlt_loop();
+}
+
+fn with_allocator<F: FnOnce(&mut BitmapFrameAllocator)>(f: F) {
+    f(FRAME_ALLOCATOR.lock().as_mut().expect("frame allocator not initialized"))
+}
+
+#[test_case]
+fn allocate_and_free() {
+    serial_print!("allocate_and_free... ");
+    with_allocator(|allocator| {
+        let free = allocator.free_frames();
+        let used = allocator.used_frames();
+        let frame = allocator.allocate_frame().expect("allocation failed");
+        assert_eq!(allocator.free_frames(), free - 1);
+        assert_eq!(allocator.used_frames(), used + 1);
+        unsafe { allocator.deallocate_frame(frame) };
+        assert_eq!(allocator.free_frames(), free);
+        assert_eq!(allocator.used_frames(), used);
+    });
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn reallocate_freed_frame() {
+    serial_print!("reallocate_freed_frame... ");
+    with_allocator(|allocator| {
+        let first = allocator.allocate_frame().expect("allocation failed");
+        unsafe { allocator.deallocate_frame(first) };
+        let second = allocator.allocate_frame().expect("allocation failed");
+        assert_eq!(first, second);
+        unsafe { allocator.deallocate_frame(second) };
+    });
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn frames_are_unique() {
+    serial_print!("frames_are_unique... ");
+    with_allocator(|allocator| {
+        let free = allocator.free_frames();
+        let mut frames: [Option<PhysFrame>; 200] = [None; 200];
+        for slot in frames.iter_mut() {
+            *slot = Some(allocator.allocate_frame().expect("allocation failed"));
+        }
+        for (i, a) in frames.iter().enumerate() {
+            for b in &frames[i + 1..] {
+                assert_ne!(a, b);
+            }
+        }
+        // free every other frame first so the hole pattern is re-used
+        for slot in frames.iter_mut().step_by(2) {
+            unsafe { allocator.deallocate_frame(slot.take().unwrap()) };
+        }
+        for slot in frames.iter_mut().filter(|s| s.is_some()) {
+            unsafe { allocator.deallocate_frame(slot.take().unwrap()) };
+        }
+        assert_eq!(allocator.free_frames(), free);
+    });
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn counts_are_consistent() {
+    serial_print!("counts_are_consistent... ");
+    with_allocator(|allocator| {
+        assert!(allocator.usable_frames() > 0);
+        assert_eq!(
+            allocator.free_frames() + allocator.used_frames(),
+            allocator.usable_frames()
+        );
+    });
+    serial_println!("[ok]");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os_626::test_panic_handler(info)
+}
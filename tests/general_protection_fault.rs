@@ -0,0 +1,28 @@
+#![no_std]
+#![no_main]
+#![feature(asm)]
+
+use core::panic::PanicInfo;
+use bootloader::{entry_point, BootInfo};
+use os_626::serial_print;
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    serial_print!("general_protection_fault::load_invalid_selector...\t");
+
+    os_626::init();
+
+    // 选择子的索引超出了GDT的范围，错误码就是该选择子
+    unsafe { asm!("mov ds, {:x}", in(reg) 0xfff8u16) };
+
+    panic!("Execution continued after loading an invalid selector");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os_626::expect_crash_report(info, "general_protection_fault::main", &[
+        "EXCEPTION: GENERAL PROTECTION FAULT (#GP, vector 13)",
+        "Error Code: 0xfff8 (GDT index 8191)",
+    ])
+}
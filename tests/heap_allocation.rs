@@ -6,27 +6,16 @@
 
 extern crate alloc;
 
-use os_626::{serial_print, serial_println, memory, allocator};
-use alloc::{ boxed::Box, vec::Vec };
+use os_626::{serial_print, serial_println, allocator};
+use alloc::{ boxed::Box, vec, vec::Vec };
 use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
-use os_626::memory::BootInfoFrameAllocator;
 
 entry_point!(main);
 
 fn main(boot_info: &'static BootInfo) -> ! {
-    use os_626::allocator;
-    use os_626::memory::{self, BootInfoFrameAllocator};
-    use x86_64::VirtAddr;
-
     os_626::init();
-    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
-    let mut mapper = unsafe { memory::init(phys_mem_offset) };
-    let mut frame_allocator = unsafe {
-        BootInfoFrameAllocator::init(&boot_info.memory_map)
-    };
-    allocator::init_heap(&mut mapper, &mut frame_allocator)
-        .expect("heap initialization failed");
+    os_626::test_init_memory_with_heap(boot_info);
 
     test_main();
     loop {}
@@ -62,6 +51,97 @@ fn many_boxes() {
     serial_println!("[ok]");
 }
 
+#[test_case]
+fn many_boxes_long_lived() {
+    serial_print!("many_boxes_long_lived... ");
+    let long_lived = Box::new(1);
+    for i in 0..10_000 {
+        let x = Box::new(i);
+        assert_eq!(*x, i);
+    }
+    assert_eq!(*long_lived, 1);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn freed_blocks_are_reused() {
+    serial_print!("freed_blocks_are_reused... ");
+    for &len in &[8usize, 100, 2000] {
+        let first = vec![0u8; len];
+        let first_addr = first.as_ptr() as usize;
+        drop(first);
+        let second = vec![1u8; len];
+        assert_eq!(second.as_ptr() as usize, first_addr);
+    }
+    drop(Box::new([0u64; 32]));
+    let size = allocator::heap_size();
+    for _ in 0..10_000 {
+        let x = Box::new([0u64; 32]);
+        assert_eq!(x[31], 0);
+    }
+    assert_eq!(allocator::heap_size(), size);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn allocation_statistics() {
+    serial_print!("allocation_statistics... ");
+    use os_626::allocator::tracking::bucket_index;
+
+    let before = allocator::stats();
+    let value = Box::new([0u8; 100]);
+    let during = allocator::stats();
+    assert_eq!(during.allocations, before.allocations + 1);
+    assert_eq!(during.live_bytes, before.live_bytes + 100);
+    assert!(during.peak_bytes >= during.live_bytes);
+    let bucket = bucket_index(100);
+    assert_eq!(during.histogram[bucket], before.histogram[bucket] + 1);
+    assert_eq!(allocator::HeapStats::bucket_limit(bucket), Some(128));
+
+    drop(value);
+    let after = allocator::stats();
+    assert_eq!(after.deallocations, before.deallocations + 1);
+    assert_eq!(after.live_bytes, before.live_bytes);
+    assert_eq!(after.live_allocations(), before.live_allocations());
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn grow_heap() {
+    serial_print!("grow_heap... ");
+    let initial_size = allocator::heap_size();
+    let mut blocks = Vec::new();
+    for i in 0..8 {
+        let block = vec![i as u8; 1024 * 1024];
+        assert_eq!(block[1024 * 1024 - 1], i as u8);
+        blocks.push(block);
+    }
+    assert!(allocator::heap_size() >= initial_size + 8 * 1024 * 1024);
+    assert!(allocator::heap_size() <= allocator::heap_limit());
+    for (i, block) in blocks.iter().enumerate() {
+        assert!(block.iter().all(|&b| b == i as u8));
+    }
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn heap_limit_is_respected() {
+    serial_print!("heap_limit_is_respected... ");
+    use alloc::alloc::{alloc, dealloc, Layout};
+
+    let size = allocator::heap_size();
+    allocator::set_heap_limit(size);
+    let layout = Layout::from_size_align(2 * size, 4096).unwrap();
+    let ptr = unsafe { alloc(layout) };
+    assert!(ptr.is_null());
+    assert_eq!(allocator::heap_size(), size);
+    allocator::set_heap_limit(allocator::HEAP_MAX_SIZE);
+    let ptr = unsafe { alloc(layout) };
+    assert!(!ptr.is_null());
+    unsafe { dealloc(ptr, layout) };
+    serial_println!("[ok]");
+}
+
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     os_626::test_panic_handler(info)
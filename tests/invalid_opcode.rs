@@ -0,0 +1,26 @@
+#![no_std]
+#![no_main]
+#![feature(asm)]
+
+use core::panic::PanicInfo;
+use bootloader::{entry_point, BootInfo};
+use os_626::serial_print;
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    serial_print!("invalid_opcode::ud2...\t");
+
+    os_626::init();
+
+    unsafe { asm!("ud2") };
+
+    panic!("Execution continued after an invalid opcode");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os_626::expect_crash_report(info, "invalid_opcode::main", &[
+        "EXCEPTION: INVALID OPCODE (#UD, vector 6)",
+    ])
+}
@@ -0,0 +1,93 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![feature(asm)]
+#![test_runner(os_626::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use os_626::{serial_print, serial_println};
+use os_626::interrupts::{self, IrqError, TrapFrame};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    os_626::init();
+
+    test_main();
+    os_626::hlt_loop();
+}
+
+static CALLS: AtomicU64 = AtomicU64::new(0);
+
+fn handler(frame: &mut TrapFrame) {
+    assert_eq!(frame.vector, u64::from(interrupts::PIC_1_OFFSET) + 5);
+    CALLS.fetch_add(1, Ordering::SeqCst);
+}
+
+fn other_handler(_frame: &mut TrapFrame) {}
+
+#[test_case]
+fn register_and_unregister() {
+    serial_print!("register_and_unregister... ");
+    assert!(interrupts::is_masked(5));
+    interrupts::register_irq(5, handler).unwrap();
+    assert!(!interrupts::is_masked(5));
+    assert_eq!(interrupts::register_irq(5, other_handler), Err(IrqError::Busy));
+    interrupts::unregister_irq(5).unwrap();
+    assert!(interrupts::is_masked(5));
+    assert_eq!(interrupts::unregister_irq(5), Err(IrqError::NotRegistered));
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn invalid_lines() {
+    serial_print!("invalid_lines... ");
+    assert_eq!(interrupts::register_irq(2, handler), Err(IrqError::InvalidIrq));
+    assert_eq!(interrupts::register_irq(16, handler), Err(IrqError::InvalidIrq));
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn timer_and_keyboard_are_registered() {
+    serial_print!("timer_and_keyboard_are_registered... ");
+    assert_eq!(interrupts::register_irq(0, other_handler), Err(IrqError::Busy));
+    assert_eq!(interrupts::register_irq(1, other_handler), Err(IrqError::Busy));
+    // 等待下一个时钟中断
+    let ticks = interrupts::irq_count(0);
+    while interrupts::irq_count(0) == ticks {
+        x86_64::instructions::hlt();
+    }
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn dispatch_to_handler() {
+    serial_print!("dispatch_to_handler... ");
+    interrupts::register_irq(5, handler).unwrap();
+    let (calls, count) = (CALLS.load(Ordering::SeqCst), interrupts::irq_count(5));
+    // 用软件中断模拟IRQ 5
+    unsafe { asm!("int 37") };
+    assert_eq!(CALLS.load(Ordering::SeqCst), calls + 1);
+    assert_eq!(interrupts::irq_count(5), count + 1);
+    interrupts::unregister_irq(5).unwrap();
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn spurious_irq_7() {
+    serial_print!("spurious_irq_7... ");
+    let (spurious, count) = (interrupts::spurious_count(7), interrupts::irq_count(7));
+    // 软件中断时IRQ 7不在服务中，和PIC产生的伪中断一样
+    unsafe { asm!("int 39") };
+    assert_eq!(interrupts::spurious_count(7), spurious + 1);
+    assert_eq!(interrupts::irq_count(7), count);
+    serial_println!("[ok]");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os_626::test_panic_handler(info)
+}
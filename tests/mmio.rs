@@ -0,0 +1,91 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os_626::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use os_626::{serial_print, serial_println};
+use os_626::memory::{self, vm, CacheMode};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use x86_64::{structures::paging::PageTableFlags, PhysAddr};
+
+const VGA_BUFFER: u64 = 0xb8000;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    os_626::init();
+    os_626::test_init_memory(boot_info);
+
+    test_main();
+    os_626::hlt_loop();
+}
+
+#[test_case]
+fn mmio_is_mapped_uncached() {
+    serial_print!("mmio_is_mapped_uncached... ");
+    let mut vga = memory::map_mmio(PhysAddr::new(VGA_BUFFER), 4096).expect("mapping failed");
+    let translation = unsafe { memory::translate(vga.base(), memory::physical_memory_offset()) }
+        .expect("mmio range not mapped");
+    assert_eq!(translation.addr, PhysAddr::new(VGA_BUFFER));
+    assert!(translation.flags.contains(PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH));
+    assert_eq!(vm::find(vga.base()).map(|r| r.kind), Some(vm::RegionKind::Mmio));
+
+    // 最后一行的第一个字符
+    let offset = 24 * 160;
+    vga.write::<u16>(offset, 0x0f41);
+    assert_eq!(vga.read::<u16>(offset), 0x0f41);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn unaligned_start_keeps_page_offset() {
+    serial_print!("unaligned_start_keeps_page_offset... ");
+    let mmio = memory::map_mmio(PhysAddr::new(VGA_BUFFER + 0x10), 0x20).expect("mapping failed");
+    assert_eq!(mmio.base().as_u64() % 4096, 0x10);
+    assert_eq!(mmio.len(), 0x20);
+    let translated = unsafe { memory::translate_addr(mmio.base(), memory::physical_memory_offset()) };
+    assert_eq!(translated, Some(PhysAddr::new(VGA_BUFFER + 0x10)));
+    mmio.unmap().expect("unmapping failed");
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn unmap_removes_the_mapping() {
+    serial_print!("unmap_removes_the_mapping... ");
+    let used = memory::FRAME_ALLOCATOR.lock().as_ref().unwrap().used_frames();
+    let mmio = memory::map_mmio_with(PhysAddr::new(VGA_BUFFER), 4096, CacheMode::WriteCombining)
+        .expect("mapping failed");
+    let base = mmio.base();
+    let direct: *const u16 = memory::phys_to_virt(PhysAddr::new(VGA_BUFFER)).as_ptr();
+    assert_eq!(mmio.read::<u16>(0), unsafe { direct.read_volatile() });
+    mmio.unmap().expect("unmapping failed");
+    assert!(unsafe { memory::translate_addr(base, memory::physical_memory_offset()) }.is_none());
+    assert!(vm::find(base).is_none());
+    // 设备内存的帧不属于帧分配器，不能被释放
+    assert!(memory::FRAME_ALLOCATOR.lock().as_ref().unwrap().used_frames() >= used);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn unmap_write_combining() {
+    serial_print!("unmap_write_combining... ");
+    let mmio = memory::map_mmio_with(PhysAddr::new(VGA_BUFFER), 4096, CacheMode::WriteCombining)
+        .expect("mapping failed");
+    let base = mmio.base();
+    let translation = unsafe { memory::translate(base, memory::physical_memory_offset()) }.unwrap();
+    assert_eq!(translation.page_size, memory::MappedPageSize::Size4KiB);
+    // 4KiB页表项中的PAT位与HUGE_PAGE位相同
+    let pat = translation.flags.contains(PageTableFlags::HUGE_PAGE);
+    assert_eq!(pat, os_626::cpu::features().pat);
+    mmio.unmap().expect("unmapping failed");
+    assert!(unsafe { memory::translate(base, memory::physical_memory_offset()) }.is_none());
+    assert!(vm::find(base).is_none());
+    serial_println!("[ok]");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os_626::test_panic_handler(info)
+}
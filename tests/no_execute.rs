@@ -0,0 +1,51 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use core::panic::PanicInfo;
+use bootloader::{entry_point, BootInfo};
+use os_626::serial_print;
+use os_626::memory;
+use x86_64::{structures::paging::PageTableFlags, VirtAddr};
+
+entry_point!(main);
+
+static DATA: u64 = 42;
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("no_execute::jump_into_heap...\t");
+
+    os_626::init();
+    os_626::test_init_memory_with_heap(boot_info);
+    memory::protect_kernel(&boot_info.memory_map);
+    let phys_mem_offset = memory::physical_memory_offset();
+
+    let flags = |addr: u64| {
+        unsafe { memory::translate(VirtAddr::new(addr), phys_mem_offset) }
+            .expect("address not mapped")
+            .flags
+    };
+    let text = flags(main as usize as u64);
+    assert!(!text.contains(PageTableFlags::WRITABLE), "kernel text is writable");
+    assert!(!text.contains(PageTableFlags::NO_EXECUTE), "kernel text is not executable");
+    assert!(flags(&DATA as *const u64 as u64).contains(PageTableFlags::NO_EXECUTE));
+
+    // 0xc3是`ret`指令，如果堆内存可执行，调用会直接返回
+    let code = Box::new([0xc3u8; 16]);
+    assert!(flags(code.as_ptr() as u64).contains(PageTableFlags::NO_EXECUTE));
+    let function: fn() = unsafe { core::mem::transmute(code.as_ptr()) };
+    function();
+
+    panic!("Execution continued after jumping into the heap");
+}
+
+/// The kernel's page fault handler includes this in the panic message for an
+/// instruction fetch from a present page.
+const EXPECTED: &str = "Access: Execute of present page";
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os_626::expect_panic_message(info, &[EXPECTED])
+}
@@ -0,0 +1,138 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os_626::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use os_626::{serial_print, serial_println};
+use os_626::memory::{
+    self,
+    fault::{self, Access, FaultPolicy, FaultRegion, PageFault, RegisterError},
+};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use x86_64::{
+    structures::{idt::PageFaultErrorCode, paging::{Page, PageTableFlags}},
+    VirtAddr,
+};
+
+const LAZY_START: u64 = 0x_5555_0000_0000;
+const CUSTOM_START: u64 = 0x_5555_1000_0000;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    os_626::init();
+    os_626::test_init_memory(boot_info);
+
+    test_main();
+    os_626::hlt_loop();
+}
+
+fn is_mapped(addr: u64) -> bool {
+    unsafe { memory::translate_addr(VirtAddr::new(addr), memory::physical_memory_offset()) }
+        .is_some()
+}
+
+#[test_case]
+fn lazy_region_maps_on_access() {
+    serial_print!("lazy_region_maps_on_access... ");
+    fault::register_region(FaultRegion {
+        name: "lazy test",
+        start: VirtAddr::new(LAZY_START),
+        size: 4 * 4096,
+        policy: FaultPolicy::LazyAllocate(PageTableFlags::WRITABLE),
+    })
+    .expect("registering the region failed");
+
+    assert!(!is_mapped(LAZY_START + 4096));
+    let ptr = (LAZY_START + 4096 + 8) as *mut u64;
+    unsafe {
+        assert_eq!(ptr.read_volatile(), 0);
+        ptr.write_volatile(0x_dead_beef);
+        assert_eq!(ptr.read_volatile(), 0x_dead_beef);
+    }
+    assert!(is_mapped(LAZY_START + 4096));
+    assert!(!is_mapped(LAZY_START));
+    serial_println!("[ok]");
+}
+
+static CUSTOM_FAULTS: AtomicUsize = AtomicUsize::new(0);
+
+fn custom_handler(fault: &PageFault) -> Result<(), &'static str> {
+    CUSTOM_FAULTS.fetch_add(1, Ordering::SeqCst);
+    fault::map_zeroed_page(Page::containing_address(fault.addr), PageTableFlags::WRITABLE)
+}
+
+#[test_case]
+fn custom_handler_is_called() {
+    serial_print!("custom_handler_is_called... ");
+    fault::register_region(FaultRegion {
+        name: "custom test",
+        start: VirtAddr::new(CUSTOM_START),
+        size: 4096,
+        policy: FaultPolicy::Handler(custom_handler),
+    })
+    .expect("registering the region failed");
+
+    let ptr = CUSTOM_START as *mut u8;
+    unsafe { ptr.write_volatile(42) };
+    unsafe { ptr.write_volatile(43) };
+    assert_eq!(CUSTOM_FAULTS.load(Ordering::SeqCst), 1);
+    assert!(fault::unregister_region(VirtAddr::new(CUSTOM_START)).is_some());
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn overlapping_regions_are_rejected() {
+    serial_print!("overlapping_regions_are_rejected... ");
+    let region = FaultRegion {
+        name: "overlap test",
+        start: VirtAddr::new(LAZY_START + 4096),
+        size: 4096,
+        policy: FaultPolicy::Guard,
+    };
+    assert_eq!(fault::register_region(region), Err(RegisterError::Overlap));
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn faults_are_classified() {
+    serial_print!("faults_are_classified... ");
+    let fault = |error_code| PageFault {
+        addr: VirtAddr::new(LAZY_START),
+        error_code,
+        instruction_pointer: VirtAddr::new(0),
+    };
+    assert_eq!(fault(PageFaultErrorCode::empty()).access(), Access::Read);
+    assert_eq!(fault(PageFaultErrorCode::CAUSED_BY_WRITE).access(), Access::Write);
+    assert_eq!(fault(PageFaultErrorCode::INSTRUCTION_FETCH).access(), Access::Execute);
+    assert!(fault(PageFaultErrorCode::PROTECTION_VIOLATION).is_protection_violation());
+    assert!(fault(PageFaultErrorCode::USER_MODE).is_user_mode());
+    assert!(fault::dispatch(&fault(PageFaultErrorCode::PROTECTION_VIOLATION)).is_err());
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn fault_while_mapper_is_held() {
+    serial_print!("fault_while_mapper_is_held... ");
+    let fault = PageFault {
+        addr: VirtAddr::new(LAZY_START + 2 * 4096),
+        error_code: PageFaultErrorCode::CAUSED_BY_WRITE,
+        instruction_pointer: VirtAddr::new(0),
+    };
+    let mapper = memory::MAPPER.lock();
+    // 等待锁会永远自旋，缺页必须失败
+    assert!(matches!(fault::dispatch(&fault), Err(fault::FaultError::Region("lazy test", _))));
+    drop(mapper);
+    assert!(!is_mapped(LAZY_START + 2 * 4096));
+    assert!(fault::dispatch(&fault).is_ok());
+    assert!(is_mapped(LAZY_START + 2 * 4096));
+    serial_println!("[ok]");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os_626::test_panic_handler(info)
+}
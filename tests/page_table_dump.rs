@@ -0,0 +1,144 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os_626::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use os_626::{serial_print, serial_println};
+use os_626::memory::{self, vm, MappedPageSize};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use x86_64::{
+    instructions::tlb,
+    registers::control::Cr3,
+    structures::paging::{
+        page_table::PageTableEntry, Mapper, Page, PageSize, PageTable, PageTableFlags, PhysFrame,
+        Size2MiB,
+    },
+    PhysAddr, VirtAddr,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    os_626::init();
+    os_626::test_init_memory(boot_info);
+
+    test_main();
+    os_626::hlt_loop();
+}
+
+fn phys_mem_offset() -> VirtAddr {
+    memory::physical_memory_offset()
+}
+
+/// Returns the level 2 entry that maps the given 2MiB page.
+fn level_2_entry(page: Page<Size2MiB>) -> &'static mut PageTableEntry {
+    let (level_4_frame, _) = Cr3::read();
+    let mut table: &'static mut PageTable =
+        unsafe { &mut *memory::phys_to_virt(level_4_frame.start_address()).as_mut_ptr() };
+    for &index in &[page.p4_index(), page.p3_index()] {
+        let next = memory::phys_to_virt(table[index].addr());
+        table = unsafe { &mut *next.as_mut_ptr() };
+    }
+    &mut table[page.p2_index()]
+}
+
+#[test_case]
+fn ranges_are_sorted_and_disjoint() {
+    serial_print!("ranges_are_sorted_and_disjoint... ");
+    let mut previous_end = 0;
+    let mut count = 0;
+    for range in unsafe { memory::mapped_ranges(phys_mem_offset()) } {
+        assert!(range.start.as_u64() >= previous_end);
+        assert!(range.size > 0);
+        assert_eq!(range.size % range.page_size.size(), 0);
+        previous_end = range.end_addr();
+        count += 1;
+    }
+    assert!(count > 0);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn ranges_match_translation() {
+    serial_print!("ranges_match_translation... ");
+    let offset = phys_mem_offset();
+    for range in unsafe { memory::mapped_ranges(offset) } {
+        let last = range.start + (range.size - 1);
+        for &addr in &[range.start, last] {
+            let translation = unsafe { memory::translate(addr, offset) }
+                .expect("mapped range does not translate");
+            assert_eq!(translation.page_size, range.page_size);
+            assert_eq!(
+                translation.addr,
+                range.phys_start + (addr - range.start)
+            );
+        }
+    }
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn vga_buffer_is_listed() {
+    serial_print!("vga_buffer_is_listed... ");
+    let vga = VirtAddr::new(0xb8000);
+    let found = unsafe { memory::mapped_ranges(phys_mem_offset()) }
+        .any(|r| r.start <= vga && vga.as_u64() < r.end_addr());
+    assert!(found);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn huge_page_with_pat_bit() {
+    serial_print!("huge_page_with_pat_bit... ");
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let region = vm::reserve("pat huge page", Size2MiB::SIZE, Size2MiB::SIZE, vm::RegionKind::Mmio, flags)
+        .expect("reserving failed");
+    let page = Page::<Size2MiB>::containing_address(region.start);
+    let frame = PhysFrame::<Size2MiB>::containing_address(PhysAddr::new(Size2MiB::SIZE));
+    let mut guard = memory::MAPPER.lock();
+    let mapper = guard.as_mut().unwrap();
+    {
+        let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+        let flags = flags | PageTableFlags::PRESENT;
+        unsafe { mapper.map_to(page, frame, flags, frame_allocator.as_mut().unwrap()) }
+            .expect("mapping failed")
+            .flush();
+    }
+    // 大页表项的PAT位是帧地址中的第12位
+    let entry = level_2_entry(page);
+    entry.set_addr(frame.start_address() + 0x1000u64, entry.flags());
+    tlb::flush(page.start_address());
+
+    let range = unsafe { memory::mapped_ranges(phys_mem_offset()) }
+        .find(|r| r.start == region.start)
+        .expect("huge page not listed");
+    assert_eq!(range.page_size, MappedPageSize::Size2MiB);
+    assert_eq!(range.phys_start, frame.start_address());
+    assert_eq!(range.size, Size2MiB::SIZE);
+    let translation = unsafe { memory::translate(region.start + 0x1234u64, phys_mem_offset()) }.unwrap();
+    assert_eq!(translation.addr, range.phys_start + 0x1234u64);
+
+    // unmap不接受带PAT位的帧地址，先清除
+    entry.set_addr(frame.start_address(), entry.flags());
+    let (unmapped, flush) = mapper.unmap(page).expect("unmapping failed");
+    flush.flush();
+    assert_eq!(unmapped, frame);
+    drop(guard);
+    vm::release(region.start).expect("releasing failed");
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn print_mappings_does_not_panic() {
+    serial_print!("print_mappings_does_not_panic... ");
+    serial_println!();
+    unsafe { memory::print_mappings(phys_mem_offset()) };
+    serial_println!("[ok]");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os_626::test_panic_handler(info)
+}
@@ -0,0 +1,96 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os_626::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use os_626::{serial_print, serial_println};
+use os_626::memory::{self, vm};
+use bootloader::bootinfo::{FrameRange, MemoryMap, MemoryRegion, MemoryRegionType};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use x86_64::{
+    instructions::tlb,
+    registers::control::Cr3,
+    structures::paging::{
+        page_table::PageTableEntry, Mapper, Page, PageSize, PageTable, PageTableFlags, PhysFrame,
+        Size2MiB,
+    },
+    PhysAddr,
+};
+
+/// A physical address above the memory of the test machine, so that the
+/// memory map built by the tests does not describe frames the kernel uses.
+const HUGE_FRAME: u64 = 0x4000_0000;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    os_626::init();
+    os_626::test_init_memory_with_heap(boot_info);
+
+    test_main();
+    os_626::hlt_loop();
+}
+
+/// Returns the level 2 entry that maps the given 2MiB page.
+fn level_2_entry(page: Page<Size2MiB>) -> &'static mut PageTableEntry {
+    let (level_4_frame, _) = Cr3::read();
+    let mut table: &'static mut PageTable =
+        unsafe { &mut *memory::phys_to_virt(level_4_frame.start_address()).as_mut_ptr() };
+    for &index in &[page.p4_index(), page.p3_index()] {
+        let next = memory::phys_to_virt(table[index].addr());
+        table = unsafe { &mut *next.as_mut_ptr() };
+    }
+    &mut table[page.p2_index()]
+}
+
+fn region(start: u64, end: u64, region_type: MemoryRegionType) -> MemoryRegion {
+    MemoryRegion { range: FrameRange::new(start, end), region_type }
+}
+
+#[test_case]
+fn huge_page_with_pat_bit() {
+    serial_print!("huge_page_with_pat_bit... ");
+    let flags = PageTableFlags::WRITABLE;
+    let reserved = vm::reserve("protect test", Size2MiB::SIZE, Size2MiB::SIZE, vm::RegionKind::Mmio, flags)
+        .expect("reserving failed");
+    let page = Page::<Size2MiB>::containing_address(reserved.start);
+    let frame = PhysFrame::<Size2MiB>::containing_address(PhysAddr::new(HUGE_FRAME));
+    {
+        let mut mapper = memory::MAPPER.lock();
+        let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+        let flags = flags | PageTableFlags::PRESENT;
+        unsafe { mapper.as_mut().unwrap().map_to(page, frame, flags, frame_allocator.as_mut().unwrap()) }
+            .expect("mapping failed")
+            .flush();
+    }
+    // 大页表项的PAT位是帧地址中的第12位
+    let entry = level_2_entry(page);
+    entry.set_addr(frame.start_address() + 0x1000u64, entry.flags());
+    tlb::flush(page.start_address());
+
+    // 大页的第一个帧是内核栈，紧随其后的帧是可用内存，按带PAT位的地址分类会得到错误的类型
+    let mut memory_map = MemoryMap::new();
+    memory_map.add_region(region(HUGE_FRAME, HUGE_FRAME + 0x1000, MemoryRegionType::KernelStack));
+    memory_map.add_region(region(HUGE_FRAME + 0x1000, HUGE_FRAME + Size2MiB::SIZE, MemoryRegionType::Usable));
+    assert!(memory::protect_kernel(&memory_map) > 0);
+
+    let translation = unsafe { memory::translate(reserved.start, memory::physical_memory_offset()) }
+        .expect("huge page not mapped");
+    assert_eq!(translation.page_size, memory::MappedPageSize::Size2MiB);
+    assert!(translation.flags.contains(PageTableFlags::NO_EXECUTE | PageTableFlags::WRITABLE));
+    // 更新标志位不能丢失PAT位
+    assert_eq!(entry.addr(), frame.start_address() + 0x1000u64);
+
+    entry.set_addr(frame.start_address(), entry.flags());
+    let (_, flush) = memory::MAPPER.lock().as_mut().unwrap().unmap(page).expect("unmapping failed");
+    flush.flush();
+    vm::release(reserved.start).expect("releasing failed");
+    serial_println!("[ok]");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os_626::test_panic_handler(info)
+}
@@ -0,0 +1,121 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os_626::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use os_626::{serial_print, serial_println, time, PanicBuffer};
+use os_626::time::rtc::{self, RtcError};
+use os_626::time::DateTime;
+use bootloader::{entry_point, BootInfo};
+use core::fmt::Write;
+use core::panic::PanicInfo;
+use core::time::Duration;
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    os_626::init();
+
+    test_main();
+    os_626::hlt_loop();
+}
+
+#[test_case]
+fn decode_bcd_12_hour() {
+    serial_print!("decode_bcd_12_hour... ");
+    // 下午12点30分59秒
+    let time = DateTime::from_registers([0x59, 0x30, 0x92, 0x18, 0x10, 0x26, 0x20], 0);
+    assert_eq!(time, DateTime { year: 2026, month: 10, day: 18, hour: 12, minute: 30, second: 59 });
+    // 上午12点是0点，下午11点是23点
+    assert_eq!(DateTime::from_registers([0, 0, 0x12, 1, 1, 0, 0x20], 0).hour, 0);
+    assert_eq!(DateTime::from_registers([0, 0, 0x91, 1, 1, 0, 0x20], 0).hour, 23);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn decode_binary_24_hour() {
+    serial_print!("decode_binary_24_hour... ");
+    let time = DateTime::from_registers([59, 30, 23, 31, 12, 99, 0], 0b110);
+    assert_eq!(time, DateTime { year: 2099, month: 12, day: 31, hour: 23, minute: 30, second: 59 });
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn unix_timestamp() {
+    serial_print!("unix_timestamp... ");
+    let epoch = DateTime { year: 1970, month: 1, day: 1, hour: 0, minute: 0, second: 0 };
+    assert_eq!(epoch.unix_timestamp(), 0);
+    let leap_day = DateTime { year: 2000, month: 2, day: 29, hour: 23, minute: 59, second: 59 };
+    assert_eq!(leap_day.unix_timestamp(), 951_868_799);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn display() {
+    serial_print!("display... ");
+    let mut buffer = PanicBuffer::new();
+    let time = DateTime { year: 2026, month: 1, day: 2, hour: 3, minute: 4, second: 5 };
+    write!(buffer, "{}", time).unwrap();
+    assert_eq!(buffer.as_str(), "2026-01-02 03:04:05");
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn now_is_valid() {
+    serial_print!("now_is_valid... ");
+    let now = rtc::now();
+    assert!(now.year >= 2020, "{}", now);
+    assert!((1..=12).contains(&now.month) && (1..=31).contains(&now.day), "{}", now);
+    assert!(now.hour < 24 && now.minute < 60 && now.second < 60, "{}", now);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn now_advances() {
+    serial_print!("now_advances... ");
+    let start = rtc::now().unix_timestamp();
+    time::sleep(Duration::from_millis(1100));
+    let elapsed = rtc::now().unix_timestamp() - start;
+    assert!((1..=2).contains(&elapsed), "{} seconds", elapsed);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn periodic_interrupt() {
+    serial_print!("periodic_interrupt... ");
+    assert_eq!(rtc::set_periodic_frequency(1000), Err(RtcError::InvalidFrequency));
+    assert_eq!(rtc::set_periodic_frequency(16384), Err(RtcError::InvalidFrequency));
+    let start = rtc::periodic_interrupts();
+    rtc::set_periodic_frequency(1024).unwrap();
+    time::sleep(Duration::from_millis(100));
+    rtc::disable_periodic();
+    // 1024Hz时100ms大约是102个中断
+    let counted = rtc::periodic_interrupts() - start;
+    assert!((80..=130).contains(&counted), "{} interrupts", counted);
+    let stopped = rtc::periodic_interrupts();
+    time::sleep(Duration::from_millis(20));
+    assert!(rtc::periodic_interrupts() <= stopped + 1);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn alarm() {
+    serial_print!("alarm... ");
+    assert_eq!(rtc::set_alarm(24, 0, 0), Err(RtcError::InvalidTime));
+    let alarms = rtc::alarms();
+    let at = (rtc::now().unix_timestamp() + 2) % 86400;
+    rtc::set_alarm((at / 3600) as u8, (at / 60 % 60) as u8, (at % 60) as u8).unwrap();
+    let deadline = time::uptime() + Duration::from_secs(4);
+    while rtc::alarms() == alarms {
+        assert!(time::uptime() < deadline, "the alarm did not fire");
+        x86_64::instructions::hlt();
+    }
+    rtc::disable_alarm();
+    serial_println!("[ok]");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os_626::test_panic_handler(info)
+}
@@ -1,28 +1,35 @@
 #![no_std]
 #![no_main]
-#![feature(abi_x86_interrupt)]
 
 use core::panic::PanicInfo;
-use lazy_static::lazy_static;
-use x86_64::structures::idt::InterruptDescriptorTable;
-use os_626::{exit_qemu, QemuExitCode, serial_print, serial_println};
-use x86_64::structures::idt::InterruptStackFrame;
+use bootloader::{entry_point, BootInfo};
+use os_626::serial_print;
+use os_626::memory;
 
-#[no_mangle]
-pub extern "C" fn _start() -> ! {
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
     serial_print!("stack_overflow::stack_overflow...\t");
 
     os_626::gdt::init();
-    init_test_idt();
+    os_626::interrupts::init_idt();
+    os_626::test_init_memory(boot_info);
+    os_626::gdt::init_stacks().expect("allocating the interrupt stacks failed");
+    memory::stack::register_current_stack("kernel")
+        .expect("registering the kernel stack guard failed");
 
     stack_overflow();
 
     panic!("Execution continued after stack overflow");
 }
 
+/// The kernel's double fault handler panics with this message when the
+/// guard page below the kernel stack is hit.
+const EXPECTED: &str = "stack overflow on stack kernel";
+
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    os_626::test_panic_handler(info);
+    os_626::expect_panic_message(info, &[EXPECTED])
 }
 
 #[allow(unconditional_recursion)]
@@ -30,30 +37,3 @@ fn stack_overflow() {
     stack_overflow(); // for each recursion, the return address is pushed
     volatile::Volatile::new(0).read(); // prevent tail recursion optimizations
 }
-
-lazy_static! {
-    static ref TEST_IDT: InterruptDescriptorTable = {
-        let mut idt = InterruptDescriptorTable::new();
-        unsafe {
-            idt.double_fault
-                .set_handler_fn(test_double_fault_handler)
-                .set_stack_index(os_626::gdt::DOUBLE_FAULT_IST_INDEX);
-        }
-
-        idt
-    };
-}
-
-
-pub fn init_test_idt() {
-    TEST_IDT.load();
-}
-
-extern "x86-interrupt" fn test_double_fault_handler(
-        _stack_frame: &mut InterruptStackFrame,
-        _error_code: u64,
-    ) -> ! {
-        serial_println!("[ok]");
-        exit_qemu(QemuExitCode::Success);
-        loop {}
-}
@@ -0,0 +1,74 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os_626::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use os_626::{serial_print, serial_println, time};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::time::Duration;
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    os_626::init();
+
+    test_main();
+    os_626::hlt_loop();
+}
+
+#[test_case]
+fn default_frequency() {
+    serial_print!("default_frequency... ");
+    assert_eq!(time::frequency(), time::DEFAULT_FREQUENCY);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn uptime_advances() {
+    serial_print!("uptime_advances... ");
+    let (ticks, uptime) = (time::ticks(), time::uptime());
+    while time::ticks() == ticks {
+        x86_64::instructions::hlt();
+    }
+    assert!(time::uptime() > uptime);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn sleep_waits() {
+    serial_print!("sleep_waits... ");
+    let start = time::uptime();
+    time::sleep(Duration::from_millis(50));
+    assert!(time::uptime() - start >= Duration::from_millis(50));
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn change_frequency() {
+    serial_print!("change_frequency... ");
+    assert_eq!(time::set_frequency(1000), 1000);
+    let (ticks, start) = (time::ticks(), time::uptime());
+    time::sleep(Duration::from_millis(100));
+    let elapsed = time::uptime() - start;
+    // 1000Hz时100ms大约是100个时钟中断
+    let counted = time::ticks() - ticks;
+    assert!((100..=102).contains(&counted), "{} ticks in {:?}", counted, elapsed);
+    assert_eq!(time::set_frequency(time::DEFAULT_FREQUENCY), time::DEFAULT_FREQUENCY);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn frequency_limits() {
+    serial_print!("frequency_limits... ");
+    // 分频系数最大为65536，约18.2Hz
+    assert_eq!(time::set_frequency(1), 18);
+    assert_eq!(time::set_frequency(time::DEFAULT_FREQUENCY), time::DEFAULT_FREQUENCY);
+    serial_println!("[ok]");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os_626::test_panic_handler(info)
+}
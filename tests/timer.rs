@@ -0,0 +1,163 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os_626::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use os_626::{serial_print, serial_println, time};
+use os_626::time::timer::{self, TimerId};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::time::Duration;
+use spin::Mutex;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    os_626::init();
+    os_626::test_init_memory_with_heap(boot_info);
+
+    test_main();
+    os_626::hlt_loop();
+}
+
+#[test_case]
+fn one_shot() {
+    serial_print!("one_shot... ");
+    let fired = Arc::new(AtomicU64::new(0));
+    let start = time::uptime();
+    let at = fired.clone();
+    timer::add_timer(Duration::from_millis(50), move || {
+        at.store(time::uptime().as_nanos() as u64, Ordering::SeqCst);
+    });
+    time::sleep(Duration::from_millis(30));
+    assert_eq!(fired.load(Ordering::SeqCst), 0);
+    time::sleep(Duration::from_millis(50));
+    let elapsed = Duration::from_nanos(fired.load(Ordering::SeqCst)) - start;
+    assert!(elapsed >= Duration::from_millis(50), "fired after {:?}", elapsed);
+    assert_eq!(timer::pending(), 0);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn deadline_order() {
+    serial_print!("deadline_order... ");
+    let order = Arc::new(Mutex::new(Vec::new()));
+    // 截止时间相同的定时器按添加的顺序执行
+    for &(delay, name) in &[(30, "30"), (10, "10a"), (20, "20"), (10, "10b"), (0, "0")] {
+        let order = order.clone();
+        timer::add_timer(Duration::from_millis(delay), move || order.lock().push(name));
+    }
+    time::sleep(Duration::from_millis(60));
+    assert_eq!(*order.lock(), ["0", "10a", "10b", "20", "30"]);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn cancel() {
+    serial_print!("cancel... ");
+    let fired = Arc::new(AtomicBool::new(false));
+    let flag = fired.clone();
+    let id = timer::add_timer(Duration::from_millis(20), move || flag.store(true, Ordering::SeqCst));
+    assert!(timer::cancel(id));
+    assert!(!timer::cancel(id));
+    time::sleep(Duration::from_millis(50));
+    assert!(!fired.load(Ordering::SeqCst));
+    assert_eq!(timer::pending(), 0);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn periodic() {
+    serial_print!("periodic... ");
+    let count = Arc::new(AtomicU64::new(0));
+    let counter = count.clone();
+    let id = timer::add_periodic(Duration::from_millis(10), move || {
+        counter.fetch_add(1, Ordering::SeqCst);
+    });
+    time::sleep(Duration::from_millis(105));
+    let counted = count.load(Ordering::SeqCst);
+    assert!((9..=11).contains(&counted), "{} calls in 105ms", counted);
+    assert!(timer::cancel(id));
+    time::sleep(Duration::from_millis(30));
+    assert_eq!(count.load(Ordering::SeqCst), counted);
+    serial_println!("[ok]");
+}
+
+static SELF_CANCELLING: Mutex<Option<TimerId>> = Mutex::new(None);
+
+#[test_case]
+fn cancel_from_callback() {
+    serial_print!("cancel_from_callback... ");
+    let count = Arc::new(AtomicU64::new(0));
+    let counter = count.clone();
+    let id = timer::add_periodic(Duration::from_millis(10), move || {
+        if counter.fetch_add(1, Ordering::SeqCst) == 2 {
+            assert!(timer::cancel(SELF_CANCELLING.lock().unwrap()));
+        }
+    });
+    *SELF_CANCELLING.lock() = Some(id);
+    time::sleep(Duration::from_millis(80));
+    assert_eq!(count.load(Ordering::SeqCst), 3);
+    assert_eq!(timer::pending(), 0);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn add_from_callback() {
+    serial_print!("add_from_callback... ");
+    let fired = Arc::new(AtomicBool::new(false));
+    let flag = fired.clone();
+    timer::add_timer(Duration::from_millis(10), move || {
+        timer::add_timer(Duration::from_millis(10), move || flag.store(true, Ordering::SeqCst));
+    });
+    time::sleep(Duration::from_millis(50));
+    assert!(fired.load(Ordering::SeqCst));
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn runs_outside_interrupt_handler() {
+    serial_print!("runs_outside_interrupt_handler... ");
+    let enabled = Arc::new(AtomicBool::new(false));
+    let flag = enabled.clone();
+    timer::add_timer(Duration::from_millis(10), move || {
+        flag.store(x86_64::instructions::interrupts::are_enabled(), Ordering::SeqCst);
+    });
+    time::sleep(Duration::from_millis(30));
+    assert!(enabled.load(Ordering::SeqCst));
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn cancelled_timer_does_not_wake() {
+    serial_print!("cancelled_timer_does_not_wake... ");
+    assert_eq!(timer::next_deadline(), None);
+    let id = timer::add_timer(Duration::from_millis(10), || panic!("cancelled timer fired"));
+    assert!(timer::next_deadline().is_some());
+    assert!(timer::cancel(id));
+    assert_eq!(timer::next_deadline(), None);
+
+    // 取消的定时器不能留在最早的截止时间中
+    let later = timer::add_timer(Duration::from_secs(60), || {});
+    let deadline = timer::next_deadline();
+    for _ in 0..1000 {
+        let id = timer::add_timer(Duration::from_millis(1), || panic!("cancelled timer fired"));
+        assert!(timer::cancel(id));
+    }
+    assert_eq!(timer::next_deadline(), deadline);
+    time::sleep(Duration::from_millis(20));
+    assert!(timer::cancel(later));
+    assert_eq!(timer::next_deadline(), None);
+    serial_println!("[ok]");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os_626::test_panic_handler(info)
+}
@@ -0,0 +1,97 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os_626::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use os_626::{serial_print, serial_println};
+use os_626::memory::{self, MappedPageSize};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::{structures::paging::MapperAllSizes, PhysAddr, VirtAddr};
+
+static PHYS_MEM_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    os_626::init();
+    PHYS_MEM_OFFSET.store(boot_info.physical_memory_offset, Ordering::SeqCst);
+
+    test_main();
+    os_626::hlt_loop();
+}
+
+fn phys_mem_offset() -> VirtAddr {
+    VirtAddr::new(PHYS_MEM_OFFSET.load(Ordering::SeqCst))
+}
+
+#[test_case]
+fn translate_vga_buffer() {
+    serial_print!("translate_vga_buffer... ");
+    let translation = unsafe { memory::translate(VirtAddr::new(0xb8000), phys_mem_offset()) }
+        .expect("vga buffer not mapped");
+    assert_eq!(translation.addr, PhysAddr::new(0xb8000));
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn translate_physical_memory_offset() {
+    serial_print!("translate_physical_memory_offset... ");
+    let offset = phys_mem_offset();
+    for &phys in &[0x1000u64, 0x20_1234, 0x40_0000, 0x7f_f008, 0x100_0abc] {
+        let translation = unsafe { memory::translate(offset + phys, offset) }
+            .expect("physical memory offset mapping missing");
+        assert_eq!(translation.addr, PhysAddr::new(phys));
+        assert_eq!(
+            translation.addr.as_u64() % translation.page_size.size(),
+            (offset + phys).as_u64() % translation.page_size.size()
+        );
+        assert_eq!(
+            unsafe { memory::translate_addr(offset + phys, offset) },
+            Some(PhysAddr::new(phys))
+        );
+    }
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn huge_pages_report_their_size() {
+    serial_print!("huge_pages_report_their_size... ");
+    use x86_64::structures::paging::PageTableFlags;
+
+    // bootloader用2MiB的页映射整个物理内存
+    let offset = phys_mem_offset();
+    let translation = unsafe { memory::translate(offset + 0x20_1234u64, offset) }
+        .expect("physical memory offset mapping missing");
+    assert_eq!(translation.page_size, MappedPageSize::Size2MiB);
+    assert!(translation.flags.contains(PageTableFlags::PRESENT | PageTableFlags::HUGE_PAGE));
+    assert_eq!(translation.addr, PhysAddr::new(0x20_1234));
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn matches_offset_page_table() {
+    serial_print!("matches_offset_page_table... ");
+    let offset = phys_mem_offset();
+    let mapper = unsafe { memory::init(offset) };
+    let addresses = [
+        VirtAddr::new(0xb8000),
+        offset + 0x12_3456u64,
+        VirtAddr::new(main as *const () as u64),
+        VirtAddr::new(0x_dead_beef_0000),
+    ];
+    for &addr in &addresses {
+        assert_eq!(
+            unsafe { memory::translate_addr(addr, offset) },
+            mapper.translate_addr(addr)
+        );
+    }
+    serial_println!("[ok]");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os_626::test_panic_handler(info)
+}
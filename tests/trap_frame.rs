@@ -0,0 +1,82 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![feature(asm)]
+#![feature(naked_functions)]
+#![test_runner(os_626::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use os_626::{serial_print, serial_println, trap_entry};
+use os_626::interrupts::{set_trap_stub, TrapFrame};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::structures::idt::InterruptDescriptorTable;
+
+entry_point!(main);
+
+lazy_static! {
+    static ref IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        set_trap_stub(&mut idt.breakpoint, breakpoint_entry);
+        idt
+    };
+}
+
+/// The frame seen by the last call of `breakpoint_handler`.
+static SAVED: Mutex<Option<TrapFrame>> = Mutex::new(None);
+
+trap_entry!(breakpoint_entry, 3, breakpoint_handler);
+
+extern "C" fn breakpoint_handler(frame: &mut TrapFrame) {
+    *SAVED.lock() = Some(frame.clone());
+    frame.rax += 1;
+    frame.r15 = !frame.r15;
+}
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    IDT.load();
+
+    test_main();
+    os_626::hlt_loop();
+}
+
+#[test_case]
+fn frame_layout() {
+    serial_print!("frame_layout... ");
+    // 15个通用寄存器、向量号、错误码和CPU压入的5个值
+    assert_eq!(core::mem::size_of::<TrapFrame>(), 22 * 8);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn registers_are_saved() {
+    serial_print!("registers_are_saved... ");
+    unsafe {
+        asm!(
+            "int3",
+            in("rax") 0x1111u64, in("rsi") 0x2222u64, in("rcx") 0x3333u64, in("r12") 0x4444u64,
+            lateout("rax") _, lateout("r15") _,
+        );
+    }
+    let frame = SAVED.lock().take().expect("breakpoint handler was not called");
+    assert_eq!((frame.rax, frame.rsi, frame.rcx, frame.r12), (0x1111, 0x2222, 0x3333, 0x4444));
+    assert_eq!((frame.vector, frame.error_code), (3, 0));
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn modified_registers_are_restored() {
+    serial_print!("modified_registers_are_restored... ");
+    let (rax, r15): (u64, u64);
+    unsafe { asm!("int3", inout("rax") 41u64 => rax, inout("r15") 0u64 => r15) };
+    assert_eq!(rax, 42);
+    assert_eq!(r15, !0);
+    serial_println!("[ok]");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os_626::test_panic_handler(info)
+}
@@ -0,0 +1,83 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os_626::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use os_626::{cpu, serial_print, serial_println};
+use os_626::memory::{
+    user::{self, UserCopyError, USER_START},
+    vm,
+};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use x86_64::{
+    registers::control::{Cr4, Cr4Flags},
+    structures::paging::{Page, PageTableFlags},
+    VirtAddr,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    os_626::init();
+    os_626::test_init_memory(boot_info);
+
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::NO_EXECUTE;
+    vm::map_zeroed_page(Page::containing_address(VirtAddr::new(USER_START)), flags)
+        .expect("mapping the user page failed");
+
+    test_main();
+    os_626::hlt_loop();
+}
+
+#[test_case]
+fn security_features_are_enabled() {
+    serial_print!("security_features_are_enabled... ");
+    let features = cpu::features();
+    let cr4 = Cr4::read();
+    assert_eq!(cr4.contains(Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION), features.smep);
+    assert_eq!(cr4.contains(Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION), features.smap);
+    assert_eq!(cr4.contains(Cr4Flags::USER_MODE_INSTRUCTION_PREVENTION), features.umip);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn copy_round_trip() {
+    serial_print!("copy_round_trip... ");
+    let addr = VirtAddr::new(USER_START + 100);
+    user::copy_to_user(addr, b"hello user").expect("copy_to_user failed");
+    let mut buf = [0u8; 10];
+    user::copy_from_user(&mut buf, addr).expect("copy_from_user failed");
+    assert_eq!(&buf, b"hello user");
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn unmapped_user_memory_returns_error() {
+    serial_print!("unmapped_user_memory_returns_error... ");
+    let mut buf = [0u8; 16];
+    let unmapped = VirtAddr::new(USER_START + 0x10_0000);
+    assert_eq!(user::copy_from_user(&mut buf, unmapped), Err(UserCopyError::Fault));
+    assert_eq!(user::copy_to_user(unmapped, &buf), Err(UserCopyError::Fault));
+    // 跨越已映射页和未映射页的拷贝同样失败
+    let crossing = VirtAddr::new(USER_START + 4096 - 8);
+    assert_eq!(user::copy_from_user(&mut buf, crossing), Err(UserCopyError::Fault));
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn kernel_addresses_are_rejected() {
+    serial_print!("kernel_addresses_are_rejected... ");
+    static KERNEL_DATA: [u8; 4] = [1, 2, 3, 4];
+    let mut buf = [0u8; 4];
+    let kernel = VirtAddr::from_ptr(&KERNEL_DATA);
+    assert_eq!(user::copy_from_user(&mut buf, kernel), Err(UserCopyError::BadAddress));
+    assert_eq!(buf, [0; 4]);
+    serial_println!("[ok]");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os_626::test_panic_handler(info)
+}
@@ -0,0 +1,111 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os_626::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use os_626::{serial_print, serial_println};
+use os_626::memory::{
+    self,
+    vm::{self, RegionKind, VmError},
+    FRAME_ALLOCATOR,
+};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use x86_64::{
+    structures::paging::{Page, PageTableFlags, PhysFrame},
+    PhysAddr, VirtAddr,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    os_626::init();
+    os_626::test_init_memory(boot_info);
+
+    test_main();
+    os_626::hlt_loop();
+}
+
+fn is_mapped(addr: VirtAddr) -> bool {
+    unsafe { memory::translate_addr(addr, memory::physical_memory_offset()) }.is_some()
+}
+
+fn used_frames() -> usize {
+    FRAME_ALLOCATOR.lock().as_ref().unwrap().used_frames()
+}
+
+#[test_case]
+fn allocate_maps_zeroed_pages() {
+    serial_print!("allocate_maps_zeroed_pages... ");
+    let region = vm::allocate("zeroed", 3 * 4096, 4096, RegionKind::Other, PageTableFlags::WRITABLE)
+        .expect("allocation failed");
+    assert_eq!(region.size, 3 * 4096);
+    assert!(region.start.as_u64() >= vm::VM_AREA_START);
+    for page in region.pages() {
+        assert!(is_mapped(page.start_address()));
+        let ptr: *mut u64 = page.start_address().as_mut_ptr();
+        unsafe {
+            assert_eq!(ptr.read_volatile(), 0);
+            ptr.write_volatile(42);
+        }
+    }
+    vm::release(region.start).expect("release failed");
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn regions_are_aligned_and_disjoint() {
+    serial_print!("regions_are_aligned_and_disjoint... ");
+    let flags = PageTableFlags::WRITABLE;
+    let small = vm::reserve("small", 4096, 4096, RegionKind::Other, flags).unwrap();
+    let aligned = vm::reserve("aligned", 4096, 0x20_0000, RegionKind::Other, flags).unwrap();
+    assert!(aligned.start.is_aligned(0x20_0000u64));
+    assert!(small.end() <= aligned.start || aligned.end() <= small.start);
+    assert_eq!(vm::find(aligned.start).map(|r| r.name), Some("aligned"));
+    assert_eq!(
+        vm::reserve_fixed("overlap", small.start, 4096, RegionKind::Other, flags).unwrap_err(),
+        VmError::Overlap
+    );
+    assert_eq!(vm::reserve("bad", 4096, 3, RegionKind::Other, flags).unwrap_err(), VmError::InvalidRange);
+    vm::release(small.start).unwrap();
+    vm::release(aligned.start).unwrap();
+    assert_eq!(vm::release(small.start).unwrap_err(), VmError::NotFound);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn release_frees_frames() {
+    serial_print!("release_frees_frames... ");
+    let flags = PageTableFlags::WRITABLE;
+    // 先分配并释放一次，使所需的页表已经存在，页表帧不会随区域释放
+    let region = vm::allocate("freed", 8 * 4096, 4096, RegionKind::Other, flags).unwrap();
+    vm::release(region.start).unwrap();
+
+    let used = used_frames();
+    let region = vm::allocate("freed", 8 * 4096, 4096, RegionKind::Other, flags).unwrap();
+    assert_eq!(used_frames(), used + 8);
+    let start = region.start;
+    vm::release(start).unwrap();
+    assert!(!is_mapped(start));
+    assert_eq!(used_frames(), used);
+    assert_eq!(vm::find(start).map(|r| r.name), None);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn mapping_a_mapped_page_fails() {
+    serial_print!("mapping_a_mapped_page_fails... ");
+    let flags = PageTableFlags::WRITABLE;
+    let region = vm::allocate("mapped", 4096, 4096, RegionKind::Other, flags).unwrap();
+    let page = Page::containing_address(region.start);
+    let frame = PhysFrame::containing_address(PhysAddr::new(0xb8000));
+    assert_eq!(unsafe { vm::map_page(page, frame, flags) }, Err(VmError::AlreadyMapped));
+    vm::release(region.start).unwrap();
+    serial_println!("[ok]");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os_626::test_panic_handler(info)
+}
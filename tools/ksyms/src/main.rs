@@ -0,0 +1,227 @@
+//! Writes the function symbols of a kernel ELF file into its `.ksyms` section,
+//! which `os_626::backtrace` uses to symbolize return addresses.
+//!
+//! Usage:
+//!
+//! ```text
+//! ksyms <kernel>                  embed the symbol table
+//! ksyms --run <kernel> [args..]   embed the symbol table, then `bootimage runner <kernel> [args..]`
+//! ```
+//!
+//! The second form is meant to be used as cargo runner.
+//!
+//! The table has the following layout, all integers are little endian:
+//!
+//! ```text
+//! magic   b"KSYMTAB1"
+//! count   u64
+//! entries count * { addr: u64, size: u64, name_offset: u32, name_len: u32 }, sorted by addr
+//! names   demangled names, name_offset is relative to the start of the table
+//! ```
+
+use std::convert::TryInto;
+use std::env;
+use std::fs;
+use std::process::{self, Command};
+
+const SECTION_NAME: &str = ".ksyms";
+const MAGIC: &[u8; 8] = b"KSYMTAB1";
+const HEADER_SIZE: usize = 16;
+const ENTRY_SIZE: usize = 24;
+
+const SHT_SYMTAB: u32 = 2;
+const STT_FUNC: u8 = 2;
+
+struct Section {
+    name: u32,
+    kind: u32,
+    offset: usize,
+    size: usize,
+    link: u32,
+}
+
+struct Symbol {
+    addr: u64,
+    size: u64,
+    name: String,
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (run, rest) = match args.first().map(String::as_str) {
+        Some("--run") => (true, &args[1..]),
+        _ => (false, &args[..]),
+    };
+    let kernel = match rest.first() {
+        Some(kernel) => kernel,
+        None => {
+            eprintln!("usage: ksyms [--run] <kernel> [runner args..]");
+            process::exit(2);
+        }
+    };
+
+    if let Err(error) = embed(kernel) {
+        eprintln!("ksyms: {}: {}", kernel, error);
+        process::exit(1);
+    }
+
+    if run {
+        let status = Command::new("bootimage")
+            .arg("runner")
+            .args(rest)
+            .status()
+            .unwrap_or_else(|error| {
+                eprintln!("ksyms: failed to run bootimage: {}", error);
+                process::exit(1);
+            });
+        process::exit(status.code().unwrap_or(1));
+    }
+}
+
+fn embed(path: &str) -> Result<(), String> {
+    let mut elf = fs::read(path).map_err(|e| e.to_string())?;
+    if elf.get(..4) != Some(b"\x7fELF") || elf[4] != 2 || elf[5] != 1 {
+        return Err("not a little endian ELF64 file".into());
+    }
+
+    let sections = sections(&elf)?;
+    let shstrndx = u16_at(&elf, 0x3e) as usize;
+    let names = sections.get(shstrndx).ok_or("missing section name table")?;
+    let section_name = |s: &Section| c_str(&elf, names.offset + s.name as usize);
+
+    let target = sections
+        .iter()
+        .find(|s| section_name(s) == SECTION_NAME)
+        .ok_or("no .ksyms section, is the kernel built with backtrace support?")?;
+    let symtab = sections
+        .iter()
+        .find(|s| s.kind == SHT_SYMTAB)
+        .ok_or("no symbol table, was the kernel stripped?")?;
+    let strtab = sections.get(symtab.link as usize).ok_or("missing symbol string table")?;
+
+    let mut symbols = Vec::new();
+    for entry in elf[symtab.offset..symtab.offset + symtab.size].chunks_exact(24) {
+        let info = entry[4];
+        let addr = u64::from_le_bytes(entry[8..16].try_into().unwrap());
+        let size = u64::from_le_bytes(entry[16..24].try_into().unwrap());
+        if info & 0xf != STT_FUNC || addr == 0 || size == 0 {
+            continue;
+        }
+        let name_offset = u32::from_le_bytes(entry[0..4].try_into().unwrap()) as usize;
+        let name = demangle(&c_str(&elf, strtab.offset + name_offset));
+        symbols.push(Symbol { addr, size, name });
+    }
+    symbols.sort_by_key(|s| s.addr);
+    symbols.dedup_by_key(|s| s.addr);
+
+    let table = encode(&symbols);
+    if table.len() > target.size {
+        return Err(format!(
+            "symbol table needs {} bytes, but .ksyms has only {}",
+            table.len(),
+            target.size
+        ));
+    }
+    let section = &mut elf[target.offset..target.offset + target.size];
+    section.iter_mut().for_each(|b| *b = 0);
+    section[..table.len()].copy_from_slice(&table);
+    fs::write(path, &elf).map_err(|e| e.to_string())
+}
+
+fn sections(elf: &[u8]) -> Result<Vec<Section>, String> {
+    let shoff = u64_at(elf, 0x28) as usize;
+    let shentsize = u16_at(elf, 0x3a) as usize;
+    let shnum = u16_at(elf, 0x3c) as usize;
+    if shoff + shnum * shentsize > elf.len() {
+        return Err("truncated section header table".into());
+    }
+    Ok((0..shnum)
+        .map(|i| {
+            let header = shoff + i * shentsize;
+            Section {
+                name: u32_at(elf, header),
+                kind: u32_at(elf, header + 4),
+                offset: u64_at(elf, header + 24) as usize,
+                size: u64_at(elf, header + 32) as usize,
+                link: u32_at(elf, header + 40),
+            }
+        })
+        .collect())
+}
+
+fn encode(symbols: &[Symbol]) -> Vec<u8> {
+    let names_start = HEADER_SIZE + symbols.len() * ENTRY_SIZE;
+    let mut table = Vec::with_capacity(names_start);
+    let mut names = Vec::new();
+    table.extend_from_slice(MAGIC);
+    table.extend_from_slice(&(symbols.len() as u64).to_le_bytes());
+    for symbol in symbols {
+        let name_offset = (names_start + names.len()) as u32;
+        table.extend_from_slice(&symbol.addr.to_le_bytes());
+        table.extend_from_slice(&symbol.size.to_le_bytes());
+        table.extend_from_slice(&name_offset.to_le_bytes());
+        table.extend_from_slice(&(symbol.name.len() as u32).to_le_bytes());
+        names.extend_from_slice(symbol.name.as_bytes());
+    }
+    table.extend_from_slice(&names);
+    table
+}
+
+/// Demangles a symbol in the legacy Rust mangling scheme and drops the hash,
+/// e.g. `_ZN6os_6269backtrace5print17h0123456789abcdefE` becomes
+/// `os_626::backtrace::print`. Other names are returned unchanged.
+fn demangle(name: &str) -> String {
+    let inner = match name.strip_prefix("_ZN").and_then(|n| n.strip_suffix('E')) {
+        Some(inner) => inner,
+        None => return name.to_string(),
+    };
+    let mut parts = Vec::new();
+    let mut rest = inner;
+    while !rest.is_empty() {
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        let len: usize = match rest[..digits].parse() {
+            Ok(len) if digits + len <= rest.len() => len,
+            _ => return name.to_string(),
+        };
+        parts.push(&rest[digits..digits + len]);
+        rest = &rest[digits + len..];
+    }
+    if let Some(last) = parts.last() {
+        if last.len() == 17 && last.starts_with('h') && last[1..].bytes().all(|b| b.is_ascii_hexdigit()) {
+            parts.pop();
+        }
+    }
+    parts.iter().map(|part| unescape(part)).collect::<Vec<_>>().join("::")
+}
+
+fn unescape(part: &str) -> String {
+    const ESCAPES: &[(&str, &str)] = &[
+        ("$SP$", "@"), ("$BP$", "*"), ("$RF$", "&"), ("$LT$", "<"), ("$GT$", ">"),
+        ("$LP$", "("), ("$RP$", ")"), ("$C$", ","), ("$u20$", " "), ("$u22$", "\""),
+        ("$u27$", "'"), ("$u2b$", "+"), ("$u3b$", ";"), ("$u5b$", "["), ("$u5d$", "]"),
+        ("$u7b$", "{"), ("$u7d$", "}"), ("$u7e$", "~"), ("..", "::"),
+    ];
+    let mut part = part.strip_prefix("_$").map_or(part.to_string(), |p| format!("${}", p));
+    for (from, to) in ESCAPES {
+        part = part.replace(from, to);
+    }
+    part
+}
+
+fn c_str(elf: &[u8], offset: usize) -> String {
+    let bytes = &elf[offset.min(elf.len())..];
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+fn u16_at(elf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(elf[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(elf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(elf[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(elf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(elf[offset..offset + 8].try_into().unwrap())
+}